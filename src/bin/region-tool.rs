@@ -0,0 +1,334 @@
+use glam::{ivec3, uvec3, IVec3};
+use rust_voxel_engine::block::Block;
+use rust_voxel_engine::chunk::Chunk;
+use rust_voxel_engine::region::{region_filename, Region, RegionReader};
+use rust_voxel_engine::world_meta::WorldMeta;
+use rust_voxel_engine::worlds::{self, Config};
+use std::fs;
+use std::process::exit;
+
+const USAGE: &str = "usage: region-tool [--world NAME | --save DIR] <command>
+the world defaults to the one played by default in the configured saves directory
+commands:
+  list                      list the regions and their chunks
+  info <x,y,z>              print every chunk of a region with its palette size and bit width
+  verify [x,y,z]            check index entries and checksums of one or all regions
+  compact [x,y,z]           rewrite one or all regions without unused space
+  dump <x,y,z> [--json]     print the blocks of the chunk at chunk position x,y,z,
+                            the json blocks are block types, their states are in the palette";
+
+fn parse_pos(text: &str) -> Option<IVec3> {
+    let coords: Vec<i32> = text
+        .split(',')
+        .map(|c| c.trim().parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if coords.len() != 3 {
+        return None;
+    }
+    Some(ivec3(coords[0], coords[1], coords[2]))
+}
+
+fn region_positions(save_dir: &str) -> Vec<IVec3> {
+    let mut positions = vec![];
+    if let Ok(dir) = fs::read_dir(String::from(save_dir) + "/region") {
+        for entry in dir.flatten() {
+            if let Some(pos) = entry.file_name().to_str().and_then(parse_pos) {
+                positions.push(pos);
+            }
+        }
+    }
+    positions.sort_by_key(|pos| (pos.x, pos.y, pos.z));
+    positions
+}
+
+fn selected_regions(save_dir: &str, arg: Option<&String>) -> Vec<IVec3> {
+    match arg {
+        Some(text) => vec![parse_pos(text).unwrap_or_else(|| fail("invalid region position"))],
+        None => region_positions(save_dir),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    exit(2);
+}
+
+fn open(save_dir: &str, pos: IVec3) -> Option<RegionReader> {
+    match RegionReader::open(&region_filename(save_dir, pos)) {
+        Ok(reader) => Some(reader),
+        Err(e) => {
+            println!("region {},{},{}: {:#}", pos.x, pos.y, pos.z, e);
+            None
+        }
+    }
+}
+
+fn list(save_dir: &str) {
+    for pos in region_positions(save_dir) {
+        if let Some(reader) = open(save_dir, pos) {
+            let entries = reader.entries();
+            let used: u64 = entries.iter().map(|entry| entry.size as u64).sum();
+            match reader.file_len() {
+                Ok(file_len) => println!(
+                    "region {},{},{}: {} chunks, {} bytes used of {}",
+                    pos.x,
+                    pos.y,
+                    pos.z,
+                    entries.len(),
+                    used,
+                    file_len
+                ),
+                Err(e) => println!("region {},{},{}: {}", pos.x, pos.y, pos.z, e),
+            }
+        }
+    }
+}
+
+fn info(save_dir: &str, pos: IVec3) {
+    let mut reader = match open(save_dir, pos) {
+        Some(reader) => reader,
+        None => return,
+    };
+    println!(
+        "region {},{},{}: version {}, generator {}",
+        pos.x, pos.y, pos.z, reader.header.version, reader.header.generator
+    );
+    for entry in reader.entries() {
+        let chunk_pos = entry.local_pos() + pos * ivec3(16, 4, 16);
+        match reader.read_chunk(&entry) {
+            Some(data) => {
+                let mut chunk = Chunk::new();
+                match chunk.deserialize(&data) {
+                    Ok(()) => println!(
+                        "  chunk {},{},{}: offset {}, size {}, palette {}, {} bits",
+                        chunk_pos.x,
+                        chunk_pos.y,
+                        chunk_pos.z,
+                        entry.offset,
+                        entry.size,
+                        chunk.palette().len(),
+                        chunk.bitsize()
+                    ),
+                    Err(e) => println!(
+                        "  chunk {},{},{}: offset {}, size {}, invalid: {}",
+                        chunk_pos.x, chunk_pos.y, chunk_pos.z, entry.offset, entry.size, e
+                    ),
+                }
+            }
+            None => println!(
+                "  chunk {},{},{}: offset {}, size {}, corrupted",
+                chunk_pos.x, chunk_pos.y, chunk_pos.z, entry.offset, entry.size
+            ),
+        }
+    }
+}
+
+fn verify(save_dir: &str, regions: Vec<IVec3>) -> bool {
+    let mut valid = true;
+    for pos in regions {
+        match open(save_dir, pos) {
+            Some(mut reader) => {
+                let problems = reader.verify();
+                if problems.is_empty() {
+                    println!("region {},{},{}: ok", pos.x, pos.y, pos.z);
+                }
+                for problem in problems {
+                    println!("region {},{},{}: {}", pos.x, pos.y, pos.z, problem);
+                    valid = false;
+                }
+            }
+            None => valid = false,
+        }
+    }
+    valid
+}
+
+fn compact(save_dir: &str, regions: Vec<IVec3>) -> bool {
+    let meta = match WorldMeta::load(save_dir) {
+        Ok(meta) => meta,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return false;
+        }
+    };
+    let mut compacted = true;
+    for pos in regions {
+        let filename = region_filename(save_dir, pos);
+        // Region::new would create an empty region in place of a missing one
+        let before = match fs::metadata(&filename) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                println!("region {},{},{}: {}: {}", pos.x, pos.y, pos.z, filename, e);
+                compacted = false;
+                continue;
+            }
+        };
+        // open through Region so the journal is applied and bad records are dropped first
+        match Region::new(
+            String::from(save_dir),
+            pos,
+            meta.settings.codec,
+            &meta.generator,
+        ) {
+            Ok(mut region) => {
+                if let Err(e) = region.flush().and_then(|_| region.shrink_to_fit()) {
+                    println!("region {},{},{}: {}", pos.x, pos.y, pos.z, e);
+                    compacted = false;
+                    continue;
+                }
+            }
+            Err(e) => {
+                println!("region {},{},{}: {:#}", pos.x, pos.y, pos.z, e);
+                compacted = false;
+                continue;
+            }
+        }
+        let after = fs::metadata(&filename).map(|m| m.len()).unwrap_or(0);
+        println!(
+            "region {},{},{}: {} -> {} bytes",
+            pos.x, pos.y, pos.z, before, after
+        );
+    }
+    compacted
+}
+
+fn dump(save_dir: &str, chunk_pos: IVec3, json: bool) -> bool {
+    let region_pos = ivec3(chunk_pos.x >> 4, chunk_pos.y >> 2, chunk_pos.z >> 4);
+    let mut reader = match open(save_dir, region_pos) {
+        Some(reader) => reader,
+        None => return false,
+    };
+    let data = match reader.entry(chunk_pos) {
+        Some(entry) => match reader.read_chunk(&entry) {
+            Some(data) => data,
+            None => {
+                eprintln!("chunk is corrupted");
+                return false;
+            }
+        },
+        None => {
+            eprintln!("chunk is not saved");
+            return false;
+        }
+    };
+    let mut chunk = Chunk::new();
+    if let Err(e) = chunk.deserialize(&data) {
+        eprintln!("chunk is invalid: {}", e);
+        return false;
+    }
+    // blocks are listed x fastest, then z, then y
+    if json {
+        let palette: Vec<String> = chunk
+            .palette()
+            .iter()
+            .map(|b| {
+                let block = Block::unpack(*b);
+                format!("{{\"type\":{},\"state\":{}}}", block.block_type, block.state)
+            })
+            .collect();
+        let mut layers = vec![];
+        for y in 0..32 {
+            let mut rows = vec![];
+            for z in 0..32 {
+                let row: Vec<String> = (0..32)
+                    .map(|x| chunk.get_block(uvec3(x, y, z)).block_type.to_string())
+                    .collect();
+                rows.push(format!("[{}]", row.join(",")));
+            }
+            layers.push(format!("[{}]", rows.join(",")));
+        }
+        println!(
+            "{{\"pos\":[{},{},{}],\"palette\":[{}],\"bitsize\":{},\"blocks\":[{}]}}",
+            chunk_pos.x,
+            chunk_pos.y,
+            chunk_pos.z,
+            palette.join(","),
+            chunk.bitsize(),
+            layers.join(",")
+        );
+    } else {
+        let palette: Vec<String> = chunk
+            .palette()
+            .iter()
+            .map(|b| Block::unpack(*b).to_string())
+            .collect();
+        println!(
+            "chunk {},{},{}: palette [{}], {} bits",
+            chunk_pos.x,
+            chunk_pos.y,
+            chunk_pos.z,
+            palette.join(", "),
+            chunk.bitsize()
+        );
+        for y in 0..32 {
+            println!("y = {}", y);
+            for z in 0..32 {
+                let row: Vec<String> = (0..32)
+                    .map(|x| chunk.get_block(uvec3(x, y, z)).to_string())
+                    .collect();
+                println!("{}", row.join(" "));
+            }
+        }
+    }
+    true
+}
+
+fn main() {
+    env_logger::init();
+    let config = Config::load();
+    let mut save_dir = config.world_dir(&config.world);
+    let mut json = false;
+    let mut args = vec![];
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--save" => {
+                save_dir = iter
+                    .next()
+                    .unwrap_or_else(|| fail("--save needs a directory"))
+            }
+            "--world" => {
+                let name = iter.next().unwrap_or_else(|| fail("--world needs a name"));
+                if let Err(e) = worlds::check_world_name(&name) {
+                    fail(&format!("{:#}", e));
+                }
+                save_dir = config.world_dir(&name)
+            }
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => args.push(arg),
+        }
+    }
+    let ok = match args.first().map(|s| s.as_str()) {
+        Some("list") => {
+            list(&save_dir);
+            true
+        }
+        Some("info") => {
+            let pos = args.get(1).and_then(|s| parse_pos(s));
+            info(
+                &save_dir,
+                pos.unwrap_or_else(|| fail("info needs a region position")),
+            );
+            true
+        }
+        Some("verify") => verify(&save_dir, selected_regions(&save_dir, args.get(1))),
+        Some("compact") => compact(&save_dir, selected_regions(&save_dir, args.get(1))),
+        Some("dump") => {
+            let pos = args.get(1).and_then(|s| parse_pos(s));
+            dump(
+                &save_dir,
+                pos.unwrap_or_else(|| fail("dump needs a chunk position")),
+                json,
+            )
+        }
+        _ => fail("missing or unknown command"),
+    };
+    if !ok {
+        exit(1);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::decoration::Feature;
+
+// each column gets the biome closest to its temperature and humidity, both from -1 to 1
+pub struct BiomeDefinition {
+    pub name: &'static str,
+    pub temperature: f32,
+    pub humidity: f32,
+    // block on top of the columns and the one under it down to filler_depth
+    pub surface: &'static str,
+    pub filler: &'static str,
+    pub filler_depth: f32,
+    // scale of the hills
+    pub amplitude: f32,
+    // feature standing on the surface blocks and its chance on each of them
+    pub feature: Feature,
+    pub decoration: f32,
+}
+
+pub const BIOMES: [BiomeDefinition; 5] = [
+    BiomeDefinition {
+        name: "plains",
+        temperature: 0.0,
+        humidity: 0.0,
+        surface: "grass",
+        filler: "dirt",
+        filler_depth: 5.0,
+        amplitude: 1.0,
+        feature: Feature::Tree,
+        decoration: 0.002,
+    },
+    BiomeDefinition {
+        name: "forest",
+        temperature: 0.1,
+        humidity: 0.6,
+        surface: "grass",
+        filler: "dirt",
+        filler_depth: 5.0,
+        amplitude: 1.2,
+        feature: Feature::Tree,
+        decoration: 0.02,
+    },
+    BiomeDefinition {
+        name: "desert",
+        temperature: 0.8,
+        humidity: -0.6,
+        surface: "sand",
+        filler: "sand",
+        filler_depth: 6.0,
+        amplitude: 0.4,
+        feature: Feature::Boulder,
+        decoration: 0.0005,
+    },
+    BiomeDefinition {
+        name: "mountains",
+        temperature: -0.2,
+        humidity: -0.5,
+        surface: "stone",
+        filler: "stone",
+        filler_depth: 1.0,
+        amplitude: 3.0,
+        feature: Feature::Boulder,
+        decoration: 0.001,
+    },
+    BiomeDefinition {
+        name: "tundra",
+        temperature: -0.8,
+        humidity: 0.2,
+        surface: "snow",
+        filler: "dirt",
+        filler_depth: 3.0,
+        amplitude: 0.8,
+        feature: Feature::Tree,
+        decoration: 0.001,
+    },
+];
+// biomes whose climate is closer than this to the climate of the nearest one
+// are blended into the shape of the terrain, so their borders have no cliffs
+const BLEND: f32 = 0.15;
+
+fn climate_distance(biome: &BiomeDefinition, temperature: f32, humidity: f32) -> f32 {
+    let (t, h) = (biome.temperature - temperature, biome.humidity - humidity);
+    (t * t + h * h).sqrt()
+}
+pub fn nearest_biome(temperature: f32, humidity: f32) -> usize {
+    let mut nearest = 0;
+    for (i, biome) in BIOMES.iter().enumerate() {
+        if climate_distance(biome, temperature, humidity)
+            < climate_distance(&BIOMES[nearest], temperature, humidity)
+        {
+            nearest = i;
+        }
+    }
+    nearest
+}
+// share of each biome in the shape of the terrain, summing to 1
+pub fn biome_weights(temperature: f32, humidity: f32) -> [f32; BIOMES.len()] {
+    let distances: [f32; BIOMES.len()] =
+        std::array::from_fn(|i| climate_distance(&BIOMES[i], temperature, humidity));
+    let nearest = distances.iter().copied().fold(f32::MAX, f32::min);
+    let mut weights = distances.map(|distance| (BLEND - (distance - nearest)).max(0.0));
+    let total: f32 = weights.iter().sum();
+    for weight in &mut weights {
+        *weight /= total;
+    }
+    weights
+}
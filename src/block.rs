@@ -1,11 +1,95 @@
+use serde::Deserialize;
+use std::fmt;
+
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Block {
     pub block_type: u16,
+    // values of the properties of the block, packed as described by Property
+    pub state: u16,
+}
+
+// axis values, blocks default to the vertical axis
+pub const AXIS_Y: u16 = 0;
+pub const AXIS_X: u16 = 1;
+pub const AXIS_Z: u16 = 2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize)]
+pub enum Property {
+    // direction of pillar like blocks such as logs
+    Axis,
+    // id of the direction the block faces
+    Facing,
+    // 0 for the bottom half, 1 for the top half
+    Half,
+    // growth stage or fluid level
+    Level,
+}
+impl Property {
+    pub const ALL: [Property; 4] = [
+        Property::Axis,
+        Property::Facing,
+        Property::Half,
+        Property::Level,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Property::Axis => "axis",
+            Property::Facing => "facing",
+            Property::Half => "half",
+            Property::Level => "level",
+        }
+    }
+    // offset and size in bits in the state
+    fn bits(&self) -> (u16, u16) {
+        match self {
+            Property::Axis => (0, 2),
+            Property::Facing => (2, 3),
+            Property::Half => (5, 1),
+            Property::Level => (6, 4),
+        }
+    }
 }
 
 impl Block {
-    pub fn is_full_block(&self) -> bool {
-        self.block_type > 0
+    pub fn new(block_type: u16) -> Self {
+        Block {
+            block_type,
+            state: 0,
+        }
+    }
+    pub fn get(&self, property: Property) -> u16 {
+        let (offset, size) = property.bits();
+        (self.state >> offset) & ((1 << size) - 1)
+    }
+    pub fn with(mut self, property: Property, value: u16) -> Self {
+        let (offset, size) = property.bits();
+        let mask = ((1 << size) - 1) << offset;
+        self.state = self.state & !mask | (value << offset) & mask;
+        self
+    }
+    // blocks are stored in chunk palettes as their type and state in a single value
+    pub fn pack(&self) -> u32 {
+        self.block_type as u32 | (self.state as u32) << 16
+    }
+    pub fn unpack(value: u32) -> Self {
+        Block {
+            block_type: value as u16,
+            state: (value >> 16) as u16,
+        }
+    }
+}
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.block_type)?;
+        if self.state != 0 {
+            let properties: Vec<String> = Property::ALL
+                .iter()
+                .filter(|property| self.get(**property) != 0)
+                .map(|property| format!("{}={}", property.name(), self.get(*property)))
+                .collect();
+            write!(f, "[{}]", properties.join(","))?;
+        }
+        Ok(())
     }
 }
@@ -1,7 +1,8 @@
-use crate::block::Block;
+use crate::block::{Block, Property, AXIS_X, AXIS_Y, AXIS_Z};
 use crate::inputs::*;
 use crate::world::World;
-use glam::{vec3, Mat4, Vec3};
+use crate::world_meta::PlayerState;
+use glam::{vec3, IVec3, Mat4, Vec3};
 use winit::keyboard::KeyCode;
 
 pub struct Camera {
@@ -10,20 +11,37 @@ pub struct Camera {
     yaw: f32,
     pitch: f32,
     speed: f32,
+    spawned: bool,
     place_cooldown: u32,
     break_cooldown: u32,
+    // opposite corners of the box exported to .vox
+    selection: [IVec3; 2],
+    // index of the placed block among the block definitions
+    selected: usize,
 }
 
 impl Camera {
-    pub fn new(speed: f32) -> Self {
+    pub fn new(player: &PlayerState) -> Self {
         Self {
-            pos: (10.0, 50.0, 10.0).into(),
+            pos: player.pos.into(),
             velocity: (0.0, 0.0, 0.0).into(),
-            yaw: 0.0,
-            pitch: 0.0,
-            speed,
+            yaw: player.yaw,
+            pitch: player.pitch,
+            speed: player.speed,
+            spawned: player.spawned,
             place_cooldown: 0,
             break_cooldown: 0,
+            selection: [IVec3::ZERO; 2],
+            selected: 3,
+        }
+    }
+    pub fn player_state(&self) -> PlayerState {
+        PlayerState {
+            pos: self.pos.into(),
+            yaw: self.yaw,
+            pitch: self.pitch,
+            speed: self.speed,
+            spawned: self.spawned,
         }
     }
 
@@ -43,7 +61,18 @@ impl Camera {
         )
     }
 
+    // air when fewer blocks are defined
+    fn selected_block(&self, world: &World) -> Block {
+        Block::new(*world.registry.defined().get(self.selected).unwrap_or(&0))
+    }
     pub fn update(&mut self, inputs: &Inputs, world: &mut World) {
+        if !self.spawned {
+            let (x, z) = (self.pos.x.floor() as i32, self.pos.z.floor() as i32);
+            if let Some(y) = world.spawn_height(x, z) {
+                self.pos.y = y as f32;
+                self.spawned = true;
+            }
+        }
         self.pitch =
             (self.pitch + (-inputs.mouse_motion_y / 300.0) as f32).clamp(-1.5707963, 1.5707963);
         self.yaw += (inputs.mouse_motion_x / 300.0) as f32;
@@ -91,7 +120,7 @@ impl Camera {
                         ),
                         false,
                     ),
-                    Block { block_type: 0 },
+                    Block::new(0),
                 );
             }
         } else {
@@ -103,22 +132,76 @@ impl Camera {
         if inputs.mouse_button_states[2] {
             if self.place_cooldown == 0 {
                 self.place_cooldown = 10;
-                world.set_block(
-                    world.raycast(
-                        self.pos,
-                        vec3(
-                            self.yaw.cos() * self.pitch.cos(),
-                            self.pitch.sin(),
-                            self.yaw.sin() * self.pitch.cos(),
-                        ),
-                        true,
-                    ),
-                    Block { block_type: 4 },
+                let dir = vec3(
+                    self.yaw.cos() * self.pitch.cos(),
+                    self.pitch.sin(),
+                    self.yaw.sin() * self.pitch.cos(),
                 );
+                let pos = world.raycast(self.pos, dir, true);
+                let mut block = self.selected_block(world);
+                // pillars are placed along the normal of the clicked face
+                if world
+                    .registry
+                    .get(block)
+                    .properties
+                    .contains(&Property::Axis)
+                {
+                    let normal = pos - world.raycast(self.pos, dir, false);
+                    let axis = if normal.x != 0 {
+                        AXIS_X
+                    } else if normal.z != 0 {
+                        AXIS_Z
+                    } else {
+                        AXIS_Y
+                    };
+                    block = block.with(Property::Axis, axis);
+                }
+                world.set_block(pos, block);
             }
         } else {
             self.place_cooldown = 0;
         }
+        let dir = vec3(
+            self.yaw.cos() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.sin() * self.pitch.cos(),
+        );
+        for key in &inputs.key_presses {
+            match key {
+                KeyCode::F1 => self.selection[0] = world.raycast(self.pos, dir, false),
+                KeyCode::F2 => self.selection[1] = world.raycast(self.pos, dir, false),
+                KeyCode::F3 => world.export_selection(self.selection[0], self.selection[1]),
+                KeyCode::F4 => world.import_model(world.raycast(self.pos, dir, true)),
+                KeyCode::Digit1 => self.selected = 0,
+                KeyCode::Digit2 => self.selected = 1,
+                KeyCode::Digit3 => self.selected = 2,
+                KeyCode::Digit4 => self.selected = 3,
+                KeyCode::Digit5 => self.selected = 4,
+                KeyCode::Digit6 => self.selected = 5,
+                KeyCode::Digit7 => self.selected = 6,
+                KeyCode::Digit8 => self.selected = 7,
+                KeyCode::Digit9 => self.selected = 8,
+                KeyCode::F5 => world.fill(
+                    self.selection[0],
+                    self.selection[1],
+                    self.selected_block(world),
+                ),
+                KeyCode::F6 => world.fill(self.selection[0], self.selection[1], Block::new(0)),
+                KeyCode::F7 => world.copy(
+                    self.selection[0],
+                    self.selection[1],
+                    world.raycast(self.pos, dir, true),
+                ),
+                KeyCode::F8 => world.toggle_greedy_meshing(),
+                KeyCode::F9 => {
+                    match world.biome(self.pos.x.floor() as i32, self.pos.z.floor() as i32) {
+                        Some(biome) => log::info!("biome {}", biome.name),
+                        None => log::info!("the world has no biomes"),
+                    }
+                }
+                _ => {}
+            }
+        }
         self.pos += self.velocity;
         self.velocity *= 0.8;
     }
@@ -1,17 +1,81 @@
 use crate::block::*;
-use glam::{dvec3, ivec3, uvec3, DVec3, IVec3, UVec3, Vec3, vec3};
-use simdnoise::NoiseBuilder;
+use crate::registry::BlockRegistry;
+use glam::{ivec3, uvec3, IVec3, UVec3};
 use std::convert::TryInto;
+use std::fmt;
+use std::sync::Arc;
 
 const BITSIZES: [u8; 13] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 16];
+#[derive(Debug)]
+pub enum ChunkDecodeError {
+    // the record holding the chunk failed its checksum or its codec
+    Record,
+    Truncated { len: usize },
+    EmptyPalette,
+    PaletteTooLarge { len: usize },
+    DataLength { expected: usize, found: usize },
+    PaletteIndex { index: usize, len: usize },
+    Height { height: u8 },
+}
+impl fmt::Display for ChunkDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkDecodeError::Record => write!(f, "corrupted record"),
+            ChunkDecodeError::Truncated { len } => write!(f, "truncated to {} bytes", len),
+            ChunkDecodeError::EmptyPalette => write!(f, "empty palette"),
+            ChunkDecodeError::PaletteTooLarge { len } => {
+                write!(f, "palette of {} blocks is too large", len)
+            }
+            ChunkDecodeError::DataLength { expected, found } => write!(
+                f,
+                "expected {} bytes of block data, found {}",
+                expected, found
+            ),
+            ChunkDecodeError::PaletteIndex { index, len } => write!(
+                f,
+                "block refers to palette entry {} of {}",
+                index, len
+            ),
+            ChunkDecodeError::Height { height } => {
+                write!(f, "height {} is outside of the chunk", height)
+            }
+        }
+    }
+}
+impl std::error::Error for ChunkDecodeError {}
+
+// converts a serialized chunk without block states to the current format,
+// the buffer is returned unchanged when it is too short to be converted
+pub fn widen_palette(buffer: &[u8]) -> Vec<u8> {
+    if buffer.len() < 2 {
+        return buffer.to_vec();
+    }
+    let palette_len = u16::from_le_bytes(buffer[..2].try_into().unwrap()) as usize;
+    if buffer.len() < 2 + palette_len * 2 {
+        return buffer.to_vec();
+    }
+    let mut widened = Vec::with_capacity(buffer.len() + palette_len * 2);
+    widened.extend_from_slice(&buffer[..2]);
+    for i in 0..palette_len {
+        widened.extend_from_slice(&buffer[2 + i * 2..4 + i * 2]);
+        widened.extend_from_slice(&[0, 0]);
+    }
+    widened.extend_from_slice(&buffer[2 + palette_len * 2..]);
+    widened
+}
+
 #[derive(Clone)]
 pub struct Chunk {
-    palette: Vec<u16>,
+    // blocks packed with Block::pack
+    palette: Vec<u32>,
     blocks_per_element: u64,
     bitsizes_index: usize,
     bitsize: u8,
     mask: u64,
     data: Vec<u64>,
+    // by column x + z * 32, local y of the highest solid block + 1, 0 when the column has none,
+    // empty until computed
+    heightmap: Vec<u8>,
 }
 impl Chunk {
     pub fn new() -> Self {
@@ -22,37 +86,93 @@ impl Chunk {
             bitsize: 0,
             mask: 0,
             data: vec![],
+            heightmap: vec![],
         }
     }
+    // the chunk is left unchanged when the buffer is invalid
     #[profiling::function]
-    pub fn deserialize(&mut self, buffer: &[u8]) {
+    pub fn deserialize(&mut self, buffer: &[u8]) -> Result<(), ChunkDecodeError> {
+        if buffer.len() < 2 {
+            return Err(ChunkDecodeError::Truncated { len: buffer.len() });
+        }
         let palette_len = u16::from_le_bytes(buffer[..2].try_into().unwrap()) as usize;
-        self.palette.clear();
-        for i in 0..palette_len {
-            self.palette.push(u16::from_le_bytes(
-                buffer[2 + i * 2..4 + i * 2].try_into().unwrap(),
-            ));
+        if palette_len == 0 {
+            return Err(ChunkDecodeError::EmptyPalette);
         }
-        let data_len = (buffer.len() - 2 - palette_len * 2) / 8;
-        let start = 2 + palette_len * 2;
-        self.data.clear();
-        for i in 0..data_len {
-            self.data.push(u64::from_le_bytes(
-                buffer[start + 8 * i..start + 8 * i + 8].try_into().unwrap(),
-            ));
+        let mut bitsizes_index = 0;
+        while palette_len > 1 << BITSIZES[bitsizes_index] {
+            bitsizes_index += 1;
+            if bitsizes_index == BITSIZES.len() {
+                return Err(ChunkDecodeError::PaletteTooLarge { len: palette_len });
+            }
         }
-        self.bitsizes_index = 0;
-        while self.palette.len() > 1 << BITSIZES[self.bitsizes_index] {
-            self.bitsizes_index += 1;
+        let start = 2 + palette_len * 4;
+        if buffer.len() < start {
+            return Err(ChunkDecodeError::Truncated { len: buffer.len() });
         }
-        self.bitsize = BITSIZES[self.bitsizes_index];
-        self.mask = (1 << self.bitsize) - 1;
-        self.blocks_per_element = if self.bitsize != 0 {
-            64 / self.bitsize as u64
+        let bitsize = BITSIZES[bitsizes_index];
+        let blocks_per_element = if bitsize != 0 {
+            64 / bitsize as u64
+        } else {
+            0
+        };
+        let data_len = if bitsize != 0 {
+            (32u64 * 32 * 32).div_ceil(blocks_per_element) as usize
+        } else {
+            0
+        };
+        // the heightmap follows the block data, chunks saved without it get it computed on load
+        let found = buffer.len() - start;
+        let heightmap_len = if found == data_len * 8 + 32 * 32 {
+            32 * 32
         } else {
             0
         };
+        if found - heightmap_len != data_len * 8 {
+            return Err(ChunkDecodeError::DataLength {
+                expected: data_len * 8,
+                found,
+            });
+        }
+        let heightmap = &buffer[buffer.len() - heightmap_len..];
+        if let Some(height) = heightmap.iter().find(|height| **height > 32) {
+            return Err(ChunkDecodeError::Height { height: *height });
+        }
+        let mask = (1u64 << bitsize) - 1;
+        let mut data = Vec::with_capacity(data_len);
+        for i in 0..data_len {
+            let element =
+                u64::from_le_bytes(buffer[start + 8 * i..start + 8 * i + 8].try_into().unwrap());
+            for j in 0..blocks_per_element {
+                let value = (element >> (j * bitsize as u64)) & mask;
+                if value as usize >= palette_len
+                    && (i as u64 * blocks_per_element + j) < 32 * 32 * 32
+                {
+                    return Err(ChunkDecodeError::PaletteIndex {
+                        index: value as usize,
+                        len: palette_len,
+                    });
+                }
+            }
+            data.push(element);
+        }
+        self.palette.clear();
+        for i in 0..palette_len {
+            self.palette.push(u32::from_le_bytes(
+                buffer[2 + i * 4..6 + i * 4].try_into().unwrap(),
+            ));
+        }
+        self.data = data;
+        self.heightmap = heightmap.to_vec();
+        self.bitsizes_index = bitsizes_index;
+        self.bitsize = bitsize;
+        self.mask = mask;
+        self.blocks_per_element = blocks_per_element;
+        Ok(())
     }
+    // palette entries are serialized as u32, buffers written before block states
+    // stored them as u16 block types and have to be converted with widen_palette
+    // the heightmap is written after the block data once computed
     #[profiling::function]
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer = vec![];
@@ -63,55 +183,103 @@ impl Chunk {
         for i in &self.data {
             buffer.extend_from_slice(&i.to_le_bytes());
         }
+        buffer.extend_from_slice(&self.heightmap);
         return buffer;
     }
+    pub fn has_heightmap(&self) -> bool {
+        !self.heightmap.is_empty()
+    }
+    // local y of the highest solid block of the column + 1, 0 when it has none or
+    // the heightmap is not computed
+    pub fn height(&self, x: u32, z: u32) -> u32 {
+        self.heightmap
+            .get((x + (z << 5)) as usize)
+            .map_or(0, |height| *height as u32)
+    }
     #[profiling::function]
-    pub fn generate(&mut self, pos: IVec3) {
-        let chunk_pos:Vec3 = vec3((pos.x * 32) as f32, (pos.y * 32) as f32, (pos.z*32)as f32);
-        let (hills,_,_) = NoiseBuilder::gradient_2d_offset(chunk_pos.x,32,chunk_pos.z,32).with_freq(0.002).generate();
-        let (valley,_,_) = NoiseBuilder::gradient_2d_offset(chunk_pos.x,32,chunk_pos.z,32).with_freq(0.01).with_seed(132487).generate();
-        let (caves1,_,_) = NoiseBuilder::gradient_3d_offset(chunk_pos.x,32,chunk_pos.y,32,chunk_pos.z,32).with_freq(0.007).generate();
-        let (caves2,_,_) = NoiseBuilder::gradient_3d_offset(chunk_pos.x,32,chunk_pos.y,32,chunk_pos.z,32).with_freq(0.01).with_seed(132487).generate();
-        for z in 0usize..32 {
-            for y in 0usize..32 {
-                for x in 0usize..32 {
-                    let height = hills[x+(z<<5)]*2000.0 - valley[x+(z<<5)].abs()*500.0;
-                    let depth = height - (pos.y * 32 + y as i32) as f32;
-                    let mut block = Block { block_type: 0 };
-                    let id = x+(y<<5)+(z<<10);
-
-                    if caves1[id].abs()+caves2[id].abs() > 0.005
-                    {
-                        if depth > 5.0 {
-                            block = Block { block_type: 3 };
-                        } else if depth > 1.0 {
-                            block = Block { block_type: 2 };
-                        } else if depth > 0.0 {
-                            block = Block { block_type: 1 };
-                        }
-                    }
-                    self.set_block(uvec3(x as u32, y as u32, z as u32), block);
-                }
+    pub fn compute_heightmap(&mut self, registry: &BlockRegistry) {
+        if self.palette.len() == 1 {
+            let height = if registry.is_solid(Block::unpack(self.palette[0])) {
+                32
+            } else {
+                0
+            };
+            self.heightmap = vec![height; 32 * 32];
+            return;
+        }
+        let mut heightmap = vec![0; 32 * 32];
+        for z in 0..32 {
+            for x in 0..32 {
+                heightmap[(x + (z << 5)) as usize] = self.column_height(x, 32, z, registry);
             }
         }
+        self.heightmap = heightmap;
+    }
+    // updates the heightmap after the block at pos changed
+    pub fn update_height(&mut self, pos: UVec3, registry: &BlockRegistry) {
+        if self.heightmap.is_empty() {
+            return self.compute_heightmap(registry);
+        }
+        let i = (pos.x + (pos.z << 5)) as usize;
+        let height = self.heightmap[i] as u32;
+        if registry.is_solid(self.get_block(pos)) {
+            self.heightmap[i] = height.max(pos.y + 1) as u8;
+        } else if pos.y + 1 == height {
+            self.heightmap[i] = self.column_height(pos.x, pos.y, pos.z, registry);
+        }
+    }
+    // recomputes the heights of the columns crossing the box after a bulk operation
+    fn update_heights(&mut self, min: UVec3, max: UVec3, registry: &BlockRegistry) {
+        if !self.has_heightmap() {
+            return;
+        }
+        for z in min.z..=max.z {
+            for x in min.x..=max.x {
+                self.heightmap[(x + (z << 5)) as usize] = self.column_height(x, 32, z, registry);
+            }
+        }
+    }
+    // height of the highest solid block of the column under y
+    fn column_height(&self, x: u32, y: u32, z: u32, registry: &BlockRegistry) -> u8 {
+        (0..y)
+            .rev()
+            .find(|y| registry.is_solid(self.get_block(uvec3(x, *y, z))))
+            .map_or(0, |y| y as u8 + 1)
     }
     pub fn get_block(&self, pos: UVec3) -> Block {
         if self.palette.len() == 1 {
-            return Block {
-                block_type: self.palette[0],
-            };
+            return Block::unpack(self.palette[0]);
         }
         let p = (pos.x + (pos.y << 5) + (pos.z << 10)) as u64;
         let b = (self.data[p as usize / self.blocks_per_element as usize]
             >> (p % self.blocks_per_element * self.bitsize as u64))
             & self.mask;
-        return Block {
-            block_type: self.palette[b as usize],
-        };
+        return Block::unpack(self.palette[b as usize]);
+    }
+    // packed blocks in the order of their index x + y * 32 + z * 1024
+    #[profiling::function]
+    pub fn unpack_blocks(&self) -> Vec<u32> {
+        if self.palette.len() == 1 {
+            return vec![self.palette[0]; 32 * 32 * 32];
+        }
+        let mut blocks = Vec::with_capacity(32 * 32 * 32);
+        for element in &self.data {
+            let mut element = *element;
+            for _ in 0..self.blocks_per_element.min(32 * 32 * 32 - blocks.len() as u64) {
+                blocks.push(self.palette[(element & self.mask) as usize]);
+                element >>= self.bitsize;
+            }
+        }
+        blocks
     }
     pub fn set_block(&mut self, pos: UVec3, block: Block) {
-        let value = self.block_to_id(block).unwrap_or_else(|| -> u16 {
-            self.palette.push(block.block_type);
+        let value = self.palette_id(block);
+        self.set_id((pos.x + (pos.y << 5) + (pos.z << 10)) as u64, value);
+    }
+    // palette entry of the block, added to the palette when missing
+    fn palette_id(&mut self, block: Block) -> u64 {
+        self.block_to_id(block).unwrap_or_else(|| -> u16 {
+            self.palette.push(block.pack());
             if self.mask < self.palette.len() as u64 - 1 {
                 profiling::scope!("Resize");
                 self.bitsizes_index += 1;
@@ -134,18 +302,160 @@ impl Chunk {
                 self.data = temp_data;
             }
             self.palette.len() as u16 - 1
-        }) as u64;
+        }) as u64
+    }
+    fn set_id(&mut self, p: u64, value: u64) {
         if self.palette.len() == 1 {
             return;
         }
-        let p = (pos.x + (pos.y << 5) + (pos.z << 10)) as u64;
         let i = p as usize / self.blocks_per_element as usize;
         let offset = p % self.blocks_per_element * self.bitsize as u64;
         self.data[i] = self.data[i] & !(self.mask << offset) | (value << offset);
     }
+    // the bulk operations work on the box between min and max, both included,
+    // and keep the heightmap up to date once it is computed
+    #[profiling::function]
+    pub fn fill(&mut self, min: UVec3, max: UVec3, block: Block, registry: &BlockRegistry) {
+        if min == UVec3::ZERO && max == UVec3::splat(31) {
+            let has_heightmap = self.has_heightmap();
+            *self = Chunk::new();
+            self.palette[0] = block.pack();
+            if has_heightmap {
+                self.compute_heightmap(registry);
+            }
+            return;
+        }
+        let value = self.palette_id(block);
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    self.set_id((x + (y << 5) + (z << 10)) as u64, value);
+                }
+            }
+        }
+        self.update_heights(min, max, registry);
+    }
+    #[profiling::function]
+    pub fn replace(
+        &mut self,
+        min: UVec3,
+        max: UVec3,
+        from: Block,
+        to: Block,
+        registry: &BlockRegistry,
+    ) {
+        let from_id = match self.block_to_id(from) {
+            Some(id) => id as usize,
+            None => return,
+        };
+        // renaming the palette entry replaces every block of the chunk at once
+        if min == UVec3::ZERO && max == UVec3::splat(31) && self.block_to_id(to).is_none() {
+            self.palette[from_id] = to.pack();
+            if self.has_heightmap() && registry.is_solid(from) != registry.is_solid(to) {
+                self.compute_heightmap(registry);
+            }
+            return;
+        }
+        if self.palette.len() == 1 {
+            self.fill(min, max, to, registry);
+            return;
+        }
+        let value = self.palette_id(to);
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let p = (x + (y << 5) + (z << 10)) as usize;
+                    if self.index(p) as usize == from_id {
+                        self.set_id(p as u64, value);
+                    }
+                }
+            }
+        }
+        self.update_heights(min, max, registry);
+    }
+    // f receives the position in the chunk and the current block and returns the new block
+    #[profiling::function]
+    pub fn apply<F: FnMut(UVec3, Block) -> Block>(
+        &mut self,
+        min: UVec3,
+        max: UVec3,
+        registry: &BlockRegistry,
+        mut f: F,
+    ) {
+        // consecutive blocks are often the same, so the palette lookup is skipped for them
+        let mut last: Option<(u32, u64)> = None;
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let pos = uvec3(x, y, z);
+                    let block = f(pos, self.get_block(pos));
+                    let value = match last {
+                        Some((packed, value)) if packed == block.pack() => value,
+                        _ => self.palette_id(block),
+                    };
+                    last = Some((block.pack(), value));
+                    self.set_id((x + (y << 5) + (z << 10)) as u64, value);
+                }
+            }
+        }
+        self.update_heights(min, max, registry);
+    }
+    // removes the palette entries no block refers to anymore and packs the blocks
+    // with the smallest bitsize that fits, down to a single entry without data
+    #[profiling::function]
+    pub fn compact(&mut self) {
+        if self.palette.len() == 1 {
+            return;
+        }
+        let mut counts = vec![0u32; self.palette.len()];
+        for i in 0..32 * 32 * 32 {
+            counts[self.index(i) as usize] += 1;
+        }
+        let mut remap = vec![0u64; self.palette.len()];
+        let mut palette = vec![];
+        for (i, count) in counts.iter().enumerate() {
+            if *count != 0 {
+                remap[i] = palette.len() as u64;
+                palette.push(self.palette[i]);
+            }
+        }
+        let mut bitsizes_index = 0;
+        while palette.len() > 1 << BITSIZES[bitsizes_index] {
+            bitsizes_index += 1;
+        }
+        if palette.len() == self.palette.len() && bitsizes_index == self.bitsizes_index {
+            return;
+        }
+        let bitsize = BITSIZES[bitsizes_index];
+        if bitsize == 0 {
+            let heightmap = std::mem::take(&mut self.heightmap);
+            *self = Chunk::new();
+            self.palette[0] = palette[0];
+            self.heightmap = heightmap;
+            return;
+        }
+        let blocks_per_element = (64 / bitsize) as u64;
+        let mut data = vec![0; (32u64 * 32 * 32).div_ceil(blocks_per_element) as usize];
+        for i in 0..32 * 32 * 32 {
+            data[i / blocks_per_element as usize] |= remap[self.index(i) as usize]
+                << (i as u64 % blocks_per_element * bitsize as u64);
+        }
+        self.palette = palette;
+        self.bitsizes_index = bitsizes_index;
+        self.bitsize = bitsize;
+        self.blocks_per_element = blocks_per_element;
+        self.mask = (1 << bitsize) - 1;
+        self.data = data;
+    }
+    // palette index of the i-th block, the chunk must not be uniform
+    fn index(&self, i: usize) -> u64 {
+        (self.data[i / self.blocks_per_element as usize]
+            >> (i as u64 % self.blocks_per_element * self.bitsize as u64))
+            & self.mask
+    }
     fn block_to_id(&self, block: Block) -> Option<u16> {
         for i in 0..self.palette.len() {
-            if self.palette[i] == block.block_type {
+            if self.palette[i] == block.pack() {
                 return Some(i as u16);
             }
         }
@@ -154,7 +464,24 @@ impl Chunk {
     pub fn data(&self) -> &Vec<u64> {
         &self.data
     }
-    pub fn palette(&self) -> &Vec<u16> {
+    pub fn palette(&self) -> &Vec<u32> {
         &self.palette
     }
+    pub fn bitsize(&self) -> u8 {
+        self.bitsize
+    }
+}
+
+// a chunk and its neighbors are passed as an array of 27 chunks,
+// the one at offset x, y, z in -1..=1 of the center is at x + 1 + 3 * (y + 1) + 9 * (z + 1)
+pub fn neighbor_index(offset: IVec3) -> usize {
+    (offset.x + 1 + 3 * (offset.y + 1) + 9 * (offset.z + 1)) as usize
+}
+// positions of the chunk at pos and its neighbors, in the order of their index
+pub fn neighborhood(pos: IVec3) -> impl Iterator<Item = IVec3> {
+    (0..27).map(move |i| pos + ivec3(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1))
+}
+// block of the neighborhood at pos relative to the origin of the center chunk
+pub fn neighborhood_block(chunks: &[Arc<Chunk>; 27], pos: IVec3) -> Block {
+    chunks[neighbor_index(pos >> 5)].get_block((pos & 31).as_uvec3())
 }
@@ -3,10 +3,13 @@ struct Output{
     @location(0) tex_coord: vec2f,
     @location(1) @interpolate(flat) tex_id: u32,
     @location(2) @interpolate(flat) light: u32,
+    @location(3) @interpolate(flat) rotated: u32,
+    @location(4) corner: vec2f,
 };
 struct Face{
     pos_dir_tex:u32,
     light:u32,
+    size:u32,
 };
 
 var<push_constant> region: u32;
@@ -29,14 +32,22 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32,@builtin(instance_index) ins
     let face_vertex_id:u32 = indices[vertex_index%6u];
     let face_id:u32 = vertex_index/6u;
     let pos_dir_tex:u32 = faces[face_id].pos_dir_tex;
+    let dir:u32 = extractBits(pos_dir_tex,18u,3u);
     let pos:vec4f = ((unpack4x8unorm(instance_index) * 255.0) + ((unpack4x8unorm(region) * 255.0) - 128.0)) * 32.0;
+    //merged faces are stretched along the axes of their texture, which is repeated on each block
+    var texture_x_axis = array<vec3f,6>(vec3f(0.0,0.0,1.0),vec3f(0.0,0.0,1.0),vec3f(1.0,0.0,0.0),vec3f(1.0,0.0,0.0),vec3f(0.0,0.0,1.0),vec3f(0.0,0.0,1.0));
+    var texture_y_axis = array<vec3f,6>(vec3f(0.0,1.0,0.0),vec3f(0.0,1.0,0.0),vec3f(0.0,1.0,0.0),vec3f(0.0,1.0,0.0),vec3f(1.0,0.0,0.0),vec3f(1.0,0.0,0.0));
+    let size:vec2f = vec2f(f32(extractBits(faces[face_id].size,0u,5u)+1u),f32(extractBits(faces[face_id].size,5u,5u)+1u));
+    let stretch:vec3f = 1.0 + texture_x_axis[dir] * (size.x - 1.0) + texture_y_axis[dir] * (size.y - 1.0);
     var ret:Output;
     ret.vertex_pos = viewproj * (vec4f(pos.xyz + vec3f(
         f32(pos_dir_tex&63u),
         f32(extractBits(pos_dir_tex,6u,6u)),
-        f32(extractBits(pos_dir_tex,12u,6u))) + face_vertex(extractBits(pos_dir_tex,18u,3u)*4u+face_vertex_id), 1.0));
-    ret.tex_coord = uv[face_vertex_id];
-    ret.tex_id = extractBits(pos_dir_tex,21u,11u);
+        f32(extractBits(pos_dir_tex,12u,6u))) + face_vertex(dir*4u+face_vertex_id) * stretch, 1.0));
+    ret.tex_coord = uv[face_vertex_id] * size;
+    ret.corner = uv[face_vertex_id];
+    ret.tex_id = extractBits(pos_dir_tex,21u,10u);
+    ret.rotated = extractBits(pos_dir_tex,31u,1u);
     ret.light = faces[face_id].light;
     return ret;
 }
@@ -45,8 +56,25 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32,@builtin(instance_index) ins
 @group(2) @binding(0) var textures:texture_2d_array<f32>;
 @group(2) @binding(1) var texture_sampler:sampler;
 
+fn shade(pos_in: Output)->vec4f{
+     let lights:vec4f = unpack4x8unorm(pos_in.light);
+     let tex_coord:vec2f = select(pos_in.tex_coord,vec2f(pos_in.tex_coord.y,1.0-pos_in.tex_coord.x),pos_in.rotated==1u);
+     let color:vec4f = textureSample(textures,texture_sampler,tex_coord,i32(pos_in.tex_id));
+     return vec4f(color.rgb*mix(mix(lights[2],lights[3],pos_in.corner.x),mix(lights[1],lights[0],pos_in.corner.x),pos_in.corner.y),color.a);
+}
+
+//opaque and cutout faces, transparent texels of cutout blocks are discarded
 @fragment
 fn fs_main(pos_in: Output)->   @location(0) vec4f {
-     let lights:vec4f = unpack4x8unorm(pos_in.light);
-     return textureSample(textures,texture_sampler,pos_in.tex_coord,i32(pos_in.tex_id))*mix(mix(lights[2],lights[3],pos_in.tex_coord.x),mix(lights[1],lights[0],pos_in.tex_coord.x),pos_in.tex_coord.y);
-}
\ No newline at end of file
+     let color:vec4f = shade(pos_in);
+     if(color.a<0.5){
+         discard;
+     }
+     return color;
+}
+
+//translucent faces are blended, they are drawn back to front
+@fragment
+fn fs_translucent(pos_in: Output)->   @location(0) vec4f {
+     return shade(pos_in);
+}
@@ -1,110 +1,79 @@
-use crate::chunk::Chunk;
+use crate::chunk::{neighborhood, Chunk};
 use crate::chunk_map::ChunkMap;
-use crate::region::Region;
+use crate::generator::{self, WorldGenerator};
+use crate::registry::BlockRegistry;
+use crate::settings::WorldSettings;
+use crate::storage::{LoadResult, StorageRequest, StorageService};
 use crate::util::threadpool::ThreadPool;
 use glam::{ivec3, IVec3};
 use std::collections::{HashMap, HashSet};
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc};
-use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
 
 pub const RENDER_DIST: i32 = 27;
 pub const RENDER_DIST_HEIGHT: i32 = 8;
 pub const RENDER_DIST2: i32 = RENDER_DIST * 2 + 1;
 pub const RENDER_DIST_HEIGHT2: i32 = RENDER_DIST_HEIGHT * 2 + 1;
 
+// chunks missing from the save get their base terrain, then their features once the
+// base terrain of their neighbors is there, so features can cross the chunk borders
+enum GenerationJob {
+    Base(IVec3),
+    Decorate(IVec3, Box<[Arc<Chunk>; 27]>),
+}
+enum Generated {
+    Base(IVec3, Arc<Chunk>),
+    Decorated(IVec3, Box<Chunk>),
+}
+
 pub struct ChunkLoader {
     loading_chunks: HashSet<IVec3>,
+    // chunks waiting for the base terrain around them to be decorated
+    undecorated: HashSet<IVec3>,
+    // base terrain around the undecorated chunks
+    base_chunks: HashMap<IVec3, Arc<Chunk>>,
+    generating_base: HashSet<IVec3>,
+    player_pos: IVec3,
     storage_thread_handle: Option<JoinHandle<()>>,
-    load_sender: crossbeam_channel::Sender<IVec3>,
-    save_sender: mpsc::Sender<(IVec3, Box<Chunk>)>,
-    threadpool_receiver: mpsc::Receiver<(IVec3, Box<Chunk>)>,
-    running: Arc<AtomicBool>,
+    storage_sender: crossbeam_channel::Sender<StorageRequest>,
+    storage_receiver: mpsc::Receiver<LoadResult>,
+    registry: Arc<BlockRegistry>,
+    threadpool: ThreadPool<GenerationJob, Generated>,
+    threadpool_receiver: mpsc::Receiver<Generated>,
 }
 impl ChunkLoader {
-    pub fn new() -> Self {
-        let (threadpool_receiver, mut threadpool) = ThreadPool::new(|pos: IVec3| {
-            let mut chunk = Box::new(Chunk::new());
-            chunk.generate(pos);
-            (pos, chunk)
-        });
-        let loading_chunks = HashSet::new();
-        let (load_sender, load_receiver) = crossbeam_channel::bounded(1500)
-            as (
-                crossbeam_channel::Sender<IVec3>,
-                crossbeam_channel::Receiver<IVec3>,
-            );
-        let (save_sender, save_receiver) = mpsc::channel()
-            as (
-                mpsc::Sender<(IVec3, Box<Chunk>)>,
-                mpsc::Receiver<(IVec3, Box<Chunk>)>,
-            );
-        let running = Arc::new(AtomicBool::new(true));
-        let loc_running = running.clone();
-        let mut region_map: HashMap<IVec3, Box<Region>> = HashMap::new();
-        let storage_thread_handle = Some(thread::spawn(move || {
-            loop {
-                match save_receiver.try_recv() {
-                    Ok(chunk) => {
-                        let region_pos: IVec3 =
-                            ivec3(chunk.0.x >> 4, chunk.0.y >> 2, chunk.0.z >> 4);
-                        let region = region_map.get_mut(&region_pos).unwrap(); //region should be created when the chunk is generated
-                        region.save_chunk(chunk.1, chunk.0);
-                        if region.chunk_count == 0 {
-                            region_map.remove(&region_pos);
-                        }
-                    }
-                    _ => {
-                        if !loc_running.load(Ordering::Relaxed) {
-                            break;
-                        }
-                        thread::sleep(Duration::from_millis(15));
-                        loop {
-                            match load_receiver.try_recv() {
-                                Ok(pos) => {
-                                    let region_pos: IVec3 =
-                                        ivec3(pos.x >> 4, pos.y >> 2, pos.z >> 4);
-                                    let region_option = region_map.get_mut(&region_pos);
-                                    let region = match region_option {
-                                        Some(region) => region,
-                                        None => {
-                                            region_map.insert(
-                                                region_pos,
-                                                Box::new(Region::new(
-                                                    String::from("save"),
-                                                    region_pos,
-                                                )),
-                                            );
-                                            region_map.get_mut(&region_pos).unwrap()
-                                        }
-                                    };
-                                    match region.load_chunk(pos) {
-                                        Some(chunk) => {
-                                            threadpool.pass((pos, chunk));
-                                        }
-                                        None => {
-                                            threadpool.send(pos).unwrap();
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
+    pub fn new(
+        save_dir: String,
+        settings: WorldSettings,
+        generator: Arc<dyn WorldGenerator>,
+        registry: Arc<BlockRegistry>,
+    ) -> Self {
+        let generator_registry = registry.clone();
+        let generator_name = String::from(generator.name());
+        let (threadpool_receiver, threadpool) = ThreadPool::new(move |job| match job {
+            GenerationJob::Base(pos) => {
+                let chunk = generator::generate_chunk(&*generator, pos, &generator_registry);
+                Generated::Base(pos, Arc::new(chunk))
             }
-        }));
+            GenerationJob::Decorate(pos, base) => {
+                let chunk = generator::decorate_chunk(&*generator, pos, &base, &generator_registry);
+                Generated::Decorated(pos, Box::new(chunk))
+            }
+        });
+        let (storage_sender, storage_receiver, storage_thread_handle) =
+            StorageService::spawn(save_dir, settings, generator_name);
         ChunkLoader {
-            loading_chunks,
-            storage_thread_handle,
-            load_sender,
-            save_sender,
+            loading_chunks: HashSet::new(),
+            undecorated: HashSet::new(),
+            base_chunks: HashMap::new(),
+            generating_base: HashSet::new(),
+            player_pos: IVec3::ZERO,
+            storage_thread_handle: Some(storage_thread_handle),
+            storage_sender,
+            storage_receiver,
+            registry,
+            threadpool,
             threadpool_receiver,
-            running,
         }
     }
     pub fn try_load(&mut self, player_pos: IVec3, pos: IVec3, chunk_map: &ChunkMap) {
@@ -113,26 +82,102 @@ impl ChunkLoader {
             && self.loading_chunks.len() < 500
             && !self.loading_chunks.contains(&chunk_pos)
         {
-            if self.load_sender.send(chunk_pos).is_ok() {
+            if self
+                .storage_sender
+                .send(StorageRequest::Load(chunk_pos))
+                .is_ok()
+            {
                 self.loading_chunks.insert(chunk_pos);
             }
         }
     }
-    pub fn save(&mut self, chunk: (IVec3, Box<Chunk>)) {
-        self.save_sender.send(chunk).unwrap();
+    // the chunk is only copied when a mesh job still holds it
+    pub fn save(&mut self, chunk: (IVec3, Arc<Chunk>)) {
+        if self
+            .storage_sender
+            .send(StorageRequest::Save(
+                chunk.0,
+                Box::new(Arc::unwrap_or_clone(chunk.1)),
+            ))
+            .is_err()
+        {
+            log::error!("storage stopped, chunk {} is lost", chunk.0);
+        }
     }
     pub fn try_get_chunk(&mut self) -> Option<(IVec3, Box<Chunk>)> {
-        let result = self.threadpool_receiver.try_recv();
-        match result {
-            Ok(chunk) => {
-                self.loading_chunks.remove(&chunk.0);
-                Some(chunk)
+        // chunks missing from the save are generated by the threadpool
+        while let Ok((pos, chunk)) = self.storage_receiver.try_recv() {
+            match chunk {
+                Some(mut chunk) => {
+                    // chunks saved before heightmaps existed
+                    if !chunk.has_heightmap() {
+                        chunk.compute_heightmap(&self.registry);
+                    }
+                    self.loading_chunks.remove(&pos);
+                    return Some((pos, chunk));
+                }
+                None => {
+                    self.undecorated.insert(pos);
+                    for neighbor in neighborhood(pos) {
+                        if !self.base_chunks.contains_key(&neighbor)
+                            && self.generating_base.insert(neighbor)
+                        {
+                            self.threadpool.send(GenerationJob::Base(neighbor)).unwrap();
+                        }
+                    }
+                    self.try_decorate(pos);
+                }
+            }
+        }
+        while let Ok(generated) = self.threadpool_receiver.try_recv() {
+            match generated {
+                Generated::Base(pos, chunk) => {
+                    self.generating_base.remove(&pos);
+                    if self.waited_for(pos) {
+                        self.base_chunks.insert(pos, chunk);
+                        for neighbor in neighborhood(pos) {
+                            self.try_decorate(neighbor);
+                        }
+                    }
+                }
+                Generated::Decorated(pos, chunk) => {
+                    self.loading_chunks.remove(&pos);
+                    return Some((pos, chunk));
+                }
+            }
+        }
+        None
+    }
+    // whether an undecorated chunk needs the base terrain of the chunk
+    fn waited_for(&self, pos: IVec3) -> bool {
+        neighborhood(pos).any(|neighbor| self.undecorated.contains(&neighbor))
+    }
+    fn try_decorate(&mut self, pos: IVec3) {
+        if !self.undecorated.contains(&pos)
+            || !neighborhood(pos).all(|neighbor| self.base_chunks.contains_key(&neighbor))
+        {
+            return;
+        }
+        let mut neighbors = neighborhood(pos);
+        let base = std::array::from_fn(|_| self.base_chunks[&neighbors.next().unwrap()].clone());
+        self.undecorated.remove(&pos);
+        self.threadpool
+            .send(GenerationJob::Decorate(pos, Box::new(base)))
+            .unwrap();
+        for neighbor in neighborhood(pos) {
+            if !self.waited_for(neighbor) {
+                self.base_chunks.remove(&neighbor);
             }
-            Err(_) => None,
         }
     }
     #[profiling::function]
     pub fn tick(&mut self, chunk_map: &ChunkMap, player_pos: IVec3) {
+        if player_pos != self.player_pos {
+            self.player_pos = player_pos;
+            let _ = self
+                .storage_sender
+                .send(StorageRequest::PlayerPos(player_pos));
+        }
         for y in (-RENDER_DIST_HEIGHT..0).rev() {
             self.try_load(player_pos, ivec3(0, y, 0), chunk_map);
         }
@@ -159,7 +204,7 @@ impl ChunkLoader {
 }
 impl Drop for ChunkLoader {
     fn drop(&mut self) {
-        self.running.store(false, Ordering::Relaxed);
+        let _ = self.storage_sender.send(StorageRequest::Shutdown);
         self.storage_thread_handle.take().unwrap().join().unwrap();
         println!("storage joined");
     }
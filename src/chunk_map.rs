@@ -1,22 +1,57 @@
 use crate::block::Block;
 use crate::chunk::Chunk;
+use crate::registry::BlockRegistry;
 use ahash::AHashMap;
-use glam::{ivec3, uvec3, IVec3};
+use glam::{ivec2, ivec3, uvec3, IVec2, IVec3, UVec3};
+use std::sync::Arc;
 
+// chunks are shared with the mesh jobs, an edit copies the chunk if a job still reads it
 pub struct ChunkMap {
-    pub hash_map: AHashMap<IVec3, Box<Chunk>>,
+    pub hash_map: AHashMap<IVec3, Arc<Chunk>>,
+    // y of the loaded chunks of each column, from the highest
+    columns: AHashMap<IVec2, Vec<i32>>,
 }
-impl ChunkMap {
-    pub fn new() -> Self {
+impl Default for ChunkMap {
+    fn default() -> Self {
         ChunkMap {
             hash_map: AHashMap::new(),
+            columns: AHashMap::new(),
         }
     }
-    pub fn get_chunk(&self, pos: IVec3) -> Option<&Box<Chunk>> {
+}
+impl ChunkMap {
+    pub fn new() -> Self {
+        ChunkMap::default()
+    }
+    pub fn insert(&mut self, pos: IVec3, chunk: Box<Chunk>) {
+        if self.hash_map.insert(pos, Arc::from(chunk)).is_none() {
+            let column = self.columns.entry(ivec2(pos.x, pos.z)).or_default();
+            let i = column.partition_point(|y| *y > pos.y);
+            column.insert(i, pos.y);
+        }
+    }
+    // removes and returns the chunks for which f returns true
+    pub fn unload<F: FnMut(IVec3) -> bool>(&mut self, mut f: F) -> Vec<(IVec3, Arc<Chunk>)> {
+        let unloaded: Vec<(IVec3, Arc<Chunk>)> =
+            self.hash_map.extract_if(|pos, _| f(*pos)).collect();
+        for (pos, _) in &unloaded {
+            let column = self.columns.get_mut(&ivec2(pos.x, pos.z)).unwrap();
+            column.retain(|y| *y != pos.y);
+            if column.is_empty() {
+                self.columns.remove(&ivec2(pos.x, pos.z));
+            }
+        }
+        unloaded
+    }
+    pub fn drain(&mut self) -> impl Iterator<Item = (IVec3, Arc<Chunk>)> + '_ {
+        self.columns.clear();
+        self.hash_map.drain()
+    }
+    pub fn get_chunk(&self, pos: IVec3) -> Option<&Arc<Chunk>> {
         self.hash_map.get(&pos)
     }
-    pub fn get_chunk_mut(&mut self, pos: IVec3) -> Option<&mut Box<Chunk>> {
-        self.hash_map.get_mut(&pos)
+    pub fn get_chunk_mut(&mut self, pos: IVec3) -> Option<&mut Chunk> {
+        self.hash_map.get_mut(&pos).map(Arc::make_mut)
     }
     pub fn get_block(&self, pos: IVec3) -> Block {
         if let Some(chunk) = self.get_chunk(ivec3(pos.x >> 5, pos.y >> 5, pos.z >> 5)) {
@@ -26,7 +61,110 @@ impl ChunkMap {
                 (pos.z as u32) & 31,
             ))
         } else {
-            Block { block_type: 0 }
+            Block::new(0)
+        }
+    }
+    // y of the highest solid block of the column among the loaded chunks
+    pub fn height(&self, x: i32, z: i32) -> Option<i32> {
+        let column = self.columns.get(&ivec2(x >> 5, z >> 5))?;
+        column.iter().find_map(|y| {
+            let height =
+                self.hash_map[&ivec3(x >> 5, *y, z >> 5)].height((x as u32) & 31, (z as u32) & 31);
+            if height > 0 {
+                Some(y * 32 + height as i32 - 1)
+            } else {
+                None
+            }
+        })
+    }
+    // chunks overlapping the box between min and max, both included,
+    // with the part of the box inside each of them
+    fn chunk_boxes(min: IVec3, max: IVec3) -> Vec<(IVec3, UVec3, UVec3)> {
+        let min_chunk = ivec3(min.x >> 5, min.y >> 5, min.z >> 5);
+        let max_chunk = ivec3(max.x >> 5, max.y >> 5, max.z >> 5);
+        let mut boxes = vec![];
+        for z in min_chunk.z..=max_chunk.z {
+            for y in min_chunk.y..=max_chunk.y {
+                for x in min_chunk.x..=max_chunk.x {
+                    let chunk_pos = ivec3(x, y, z);
+                    let origin = chunk_pos * 32;
+                    boxes.push((
+                        chunk_pos,
+                        (min.max(origin) - origin).as_uvec3(),
+                        (max.min(origin + 31) - origin).as_uvec3(),
+                    ));
+                }
+            }
+        }
+        boxes
+    }
+    // the bulk operations only change loaded chunks, min must not be greater than max
+    #[profiling::function]
+    pub fn fill(&mut self, min: IVec3, max: IVec3, block: Block, registry: &BlockRegistry) {
+        for (chunk_pos, local_min, local_max) in ChunkMap::chunk_boxes(min, max) {
+            if let Some(chunk) = self.get_chunk_mut(chunk_pos) {
+                chunk.fill(local_min, local_max, block, registry);
+                chunk.compact();
+            }
+        }
+    }
+    #[profiling::function]
+    pub fn replace(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        from: Block,
+        to: Block,
+        registry: &BlockRegistry,
+    ) {
+        for (chunk_pos, local_min, local_max) in ChunkMap::chunk_boxes(min, max) {
+            if let Some(chunk) = self.get_chunk_mut(chunk_pos) {
+                chunk.replace(local_min, local_max, from, to, registry);
+                chunk.compact();
+            }
+        }
+    }
+    // f receives the world position and the current block and returns the new block
+    #[profiling::function]
+    pub fn apply<F: FnMut(IVec3, Block) -> Block>(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        registry: &BlockRegistry,
+        mut f: F,
+    ) {
+        for (chunk_pos, local_min, local_max) in ChunkMap::chunk_boxes(min, max) {
+            if let Some(chunk) = self.get_chunk_mut(chunk_pos) {
+                let origin = chunk_pos * 32;
+                chunk.apply(local_min, local_max, registry, |pos, block| {
+                    f(origin + pos.as_ivec3(), block)
+                });
+                chunk.compact();
+            }
+        }
+    }
+    // the box is read entirely before being written, so the destination may overlap it
+    #[profiling::function]
+    pub fn copy(&mut self, min: IVec3, max: IVec3, dest: IVec3, registry: &BlockRegistry) {
+        let size = (max - min + 1).as_uvec3();
+        let mut blocks = vec![Block::new(0); (size.x * size.y * size.z) as usize];
+        for (chunk_pos, local_min, local_max) in ChunkMap::chunk_boxes(min, max) {
+            if let Some(chunk) = self.get_chunk(chunk_pos) {
+                let offset = (chunk_pos * 32 + local_min.as_ivec3() - min).as_uvec3();
+                for z in local_min.z..=local_max.z {
+                    for y in local_min.y..=local_max.y {
+                        for x in local_min.x..=local_max.x {
+                            let pos = uvec3(x, y, z) - local_min + offset;
+                            blocks[(pos.x + size.x * (pos.y + size.y * pos.z)) as usize] =
+                                chunk.get_block(uvec3(x, y, z));
+                        }
+                    }
+                }
+            }
         }
+        self.apply(dest, dest + size.as_ivec3() - 1, registry, |pos, _| {
+            let pos = (pos - dest).as_uvec3();
+            blocks[(pos.x + size.x * (pos.y + size.y * pos.z)) as usize]
+        });
     }
 }
@@ -1,25 +1,19 @@
-use crate::mesh::Face;
+use crate::direction::Direction;
+use crate::mesher::{ChunkMesh, Face};
 use crate::mipmap;
+use crate::registry::texture_path;
 use crate::render_region::{RenderRegion, RENDER_REGION_CHUNKS};
 use crate::texture::*;
+use crate::util::assets::asset_path;
 use glam::{ivec3, IVec3, Vec3};
 use std::borrow::Cow;
 use std::collections::HashMap;
-
-pub const IMAGES: [&str; 8] = [
-    "textures/grass_side.png",
-    "textures/grass_top.png",
-    "textures/grass_bottom.png",
-    "textures/stone.png",
-    "textures/brick.png",
-    "textures/log_side.png",
-    "textures/log_top.png",
-    "textures/leaves.png",
-];
+use std::ops::Range;
 
 pub struct ChunkRenderer {
     texture_array: Texture,
     render_pipeline: wgpu::RenderPipeline,
+    translucent_pipeline: wgpu::RenderPipeline,
     occlusion_pipeline: wgpu::RenderPipeline,
     compute_pipeline: wgpu::ComputePipeline,
     diffuse_bind_group: wgpu::BindGroup,
@@ -27,6 +21,15 @@ pub struct ChunkRenderer {
     face_bind_group_layout: wgpu::BindGroupLayout,
     occlusion_bind_group_layout: wgpu::BindGroupLayout,
     scan_bind_group_layout: wgpu::BindGroupLayout,
+    // translucent faces by chunk, they are drawn after everything else sorted back to front
+    translucent_faces: HashMap<IVec3, Vec<Face>>,
+    translucent_buffer: wgpu::Buffer,
+    translucent_buffer_len: u64,
+    translucent_bind_group: wgpu::BindGroup,
+    // region relative to the player, chunk in the region and faces of the sorted runs of faces
+    translucent_draws: Vec<(IVec3, u32, Range<u32>)>,
+    // camera position of the last sort, None when the faces changed since
+    sorted_from: Option<Vec3>,
 }
 impl ChunkRenderer {
     #[profiling::function]
@@ -37,6 +40,7 @@ impl ChunkRenderer {
         config: &wgpu::SurfaceConfiguration,
         context_bind_group_layout: &wgpu::BindGroupLayout,
         depth_bind_group_layout: &wgpu::BindGroupLayout,
+        textures: &[String],
     ) -> Self {
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -61,8 +65,9 @@ impl ChunkRenderer {
                 label: Some("texture_bind_group_layout"),
             });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            // merged faces repeat the texture on each block
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Nearest,
             min_filter: wgpu::FilterMode::Nearest,
@@ -70,8 +75,11 @@ impl ChunkRenderer {
             ..Default::default()
         });
         let mut images = vec![];
-        for img in IMAGES {
-            images.push(image::open(img).expect("texture could not be loaded"));
+        for texture in textures {
+            images.push(
+                image::open(asset_path(&texture_path(texture)))
+                    .unwrap_or_else(|e| panic!("texture {} could not be loaded: {}", texture, e)),
+            );
         }
         let texture_array =
             Texture::from_images(device, queue, &images, Some("texture_array")).unwrap();
@@ -79,7 +87,7 @@ impl ChunkRenderer {
             init_encoder,
             &device,
             &texture_array.texture,
-            IMAGES.len() as u32,
+            textures.len() as u32,
             MIP_LEVEL_COUNT,
         );
         let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -310,10 +318,54 @@ impl ChunkRenderer {
             multisample: Default::default(),
             multiview: None,
         });
+        let translucent_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Translucent Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_translucent",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format.into(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            // translucent faces are tested against the opaque ones but don't hide each other
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Greater,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: Default::default(),
+            multiview: None,
+        });
+        let translucent_buffer_len = 1024 * std::mem::size_of::<Face>() as u64;
+        let (translucent_buffer, translucent_bind_group) = ChunkRenderer::create_translucent_buffer(
+            device,
+            &region_bind_group_layout,
+            translucent_buffer_len,
+        );
 
         Self {
             texture_array,
             render_pipeline,
+            translucent_pipeline,
             occlusion_pipeline,
             compute_pipeline,
             diffuse_bind_group,
@@ -321,23 +373,57 @@ impl ChunkRenderer {
             face_bind_group_layout: region_bind_group_layout,
             occlusion_bind_group_layout,
             scan_bind_group_layout,
+            translucent_faces: HashMap::new(),
+            translucent_buffer,
+            translucent_buffer_len,
+            translucent_bind_group,
+            translucent_draws: vec![],
+            sorted_from: None,
         }
     }
+    fn create_translucent_buffer(
+        device: &wgpu::Device,
+        face_bind_group_layout: &wgpu::BindGroupLayout,
+        size: u64,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Translucent Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("translucent face bind group"),
+            layout: face_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        (buffer, bind_group)
+    }
 
     #[profiling::function]
     pub fn add_chunk(
         &mut self,
         pos: IVec3,
-        data: &mut [Vec<Face>; 6],
+        data: &mut ChunkMesh,
         queue: &wgpu::Queue,
         device: &wgpu::Device,
     ) {
+        if data.translucent.is_empty() {
+            self.translucent_faces.remove(&pos);
+        } else {
+            self.translucent_faces
+                .insert(pos, std::mem::take(&mut data.translucent));
+        }
+        self.sorted_from = None;
         let ipos = ivec3(pos.x & !15, pos.y & !7, pos.z & !15);
         let region = self.map.get_mut(&ipos);
         if let Some(region) = region {
             region.add_chunk(
                 ivec3(pos.x & 15, pos.y & 7, pos.z & 15),
-                data,
+                &mut data.faces,
                 queue,
                 device,
                 &self.face_bind_group_layout,
@@ -356,6 +442,9 @@ impl ChunkRenderer {
     }
     #[profiling::function]
     pub fn remove_chunk(&mut self, pos: IVec3, queue: &mut wgpu::Queue) {
+        if self.translucent_faces.remove(&pos).is_some() {
+            self.sorted_from = None;
+        }
         let ipos = ivec3(pos.x & !15, pos.y & !7, pos.z & !15);
         let region = self.map.get_mut(&ipos);
         if let Some(region) = region {
@@ -461,6 +550,85 @@ impl ChunkRenderer {
             );
             region.1.draw(&mut render_pass);
         }
+        render_pass.set_pipeline(&self.translucent_pipeline);
+        render_pass.set_bind_group(1, &self.translucent_bind_group, &[]);
+        for (relative_region_pos, instance, faces) in &self.translucent_draws {
+            render_pass.set_push_constants(
+                wgpu::ShaderStages::VERTEX,
+                0,
+                &[
+                    (relative_region_pos.x + 128) as u8,
+                    (relative_region_pos.y + 128) as u8,
+                    (relative_region_pos.z + 128) as u8,
+                    0u8,
+                ],
+            );
+            render_pass.draw(faces.start * 6..faces.end * 6, *instance..*instance + 1);
+        }
+    }
+    // writes the translucent faces from the farthest to the closest to the camera,
+    // they are only sorted again when the camera moved or the faces changed
+    #[profiling::function]
+    pub fn sort_translucent(
+        &mut self,
+        camera_pos: Vec3,
+        player_pos: IVec3,
+        queue: &wgpu::Queue,
+        device: &wgpu::Device,
+    ) {
+        if self.sorted_from == Some(camera_pos) {
+            return;
+        }
+        self.sorted_from = Some(camera_pos);
+        // positions relative to the chunk of the player to keep the precision far from the origin
+        let camera_pos = camera_pos - (player_pos * 32).as_vec3();
+        let mut faces = vec![];
+        for (chunk_pos, chunk_faces) in &self.translucent_faces {
+            let origin = ((*chunk_pos - player_pos) * 32).as_vec3();
+            for face in chunk_faces {
+                let pos = Vec3::new(
+                    (face.pos_dir_tex & 63) as f32,
+                    (face.pos_dir_tex >> 6 & 63) as f32,
+                    (face.pos_dir_tex >> 12 & 63) as f32,
+                );
+                let normal = Direction {
+                    id: (face.pos_dir_tex >> 18 & 7) as u8,
+                }
+                .get_norm()
+                .as_vec3();
+                let center = origin + pos + 0.5 + normal * 0.5;
+                faces.push((center.distance_squared(camera_pos), *chunk_pos, *face));
+            }
+        }
+        faces.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        let size = (faces.len() * std::mem::size_of::<Face>()) as u64;
+        if size > self.translucent_buffer_len {
+            self.translucent_buffer.destroy();
+            self.translucent_buffer_len = size * 2;
+            (self.translucent_buffer, self.translucent_bind_group) =
+                ChunkRenderer::create_translucent_buffer(
+                    device,
+                    &self.face_bind_group_layout,
+                    self.translucent_buffer_len,
+                );
+        }
+        let sorted: Vec<Face> = faces.iter().map(|face| face.2).collect();
+        queue.write_buffer(&self.translucent_buffer, 0, bytemuck::cast_slice(&sorted));
+        // consecutive faces of the same chunk are drawn together
+        self.translucent_draws.clear();
+        let mut start = 0;
+        for i in 1..=faces.len() {
+            if i == faces.len() || faces[i].1 != faces[start].1 {
+                let pos = faces[start].1;
+                let region_pos = ivec3(pos.x & !15, pos.y & !7, pos.z & !15);
+                self.translucent_draws.push((
+                    region_pos - player_pos,
+                    (pos.x & 15 | (pos.y & 7) << 8 | (pos.z & 15) << 16) as u32,
+                    start as u32..i as u32,
+                ));
+                start = i;
+            }
+        }
     }
 }
 impl Drop for ChunkRenderer {
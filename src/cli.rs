@@ -0,0 +1,132 @@
+use rust_voxel_engine::generator::DEFAULT_GENERATOR;
+use rust_voxel_engine::world_meta::timestamp;
+use rust_voxel_engine::worlds::{self, Config};
+use std::process::exit;
+
+const USAGE: &str = "usage: rust_voxel_engine [options]
+  --world NAME              play the world NAME of the saves directory, created if missing
+  --world-dir DIR           play the world stored in DIR
+  --saves DIR               directory containing the worlds
+  --new                     create the world instead of opening it, fails if it exists
+  --seed N                  seed of the created world
+  --generator NAME          generator of the created world, noise or flat
+  --list                    list the worlds and exit
+  --delete NAME             delete a world and exit
+  --copy FROM TO            duplicate a world and exit";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    exit(2);
+}
+
+fn age(time: u64) -> String {
+    let seconds = timestamp().saturating_sub(time);
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}min ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+// handles the command line, returns the directory of the world to play
+// or exits when only managing worlds
+pub fn select_world() -> String {
+    let mut config = Config::load();
+    let mut world_dir = None;
+    let mut new = false;
+    let mut seed = None;
+    let mut generator = String::from(DEFAULT_GENERATOR);
+    let mut list = false;
+    let mut delete = None;
+    let mut copy = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "--world" => config.world = value(),
+            "--world-dir" => world_dir = Some(value()),
+            "--saves" => config.saves_dir = value(),
+            "--new" => new = true,
+            "--seed" => {
+                seed = Some(
+                    value()
+                        .parse::<u64>()
+                        .unwrap_or_else(|_| fail("the seed must be an unsigned integer")),
+                )
+            }
+            "--generator" => generator = value(),
+            "--list" => list = true,
+            "--delete" => delete = Some(value()),
+            "--copy" => {
+                let from = value();
+                copy = Some((from, value()))
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                if let Some(path) = Config::path() {
+                    println!("defaults are read from {}", path.display());
+                }
+                exit(0);
+            }
+            _ => fail(&format!("unknown option {}", arg)),
+        }
+    }
+    match worlds::migrate_legacy_save(worlds::LEGACY_SAVE_DIR, &config.saves_dir) {
+        Ok(Some(dir)) => println!(
+            "moved the world of ./{} to {}",
+            worlds::LEGACY_SAVE_DIR,
+            dir
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!("{:#}", e),
+    }
+    if list {
+        for (name, meta) in worlds::list_worlds(&config.saves_dir) {
+            match meta {
+                Ok(meta) => println!(
+                    "{}: seed {}, generator {}, last played {}",
+                    name,
+                    meta.seed,
+                    meta.generator,
+                    age(meta.last_played)
+                ),
+                Err(e) => println!("{}: {:#}", name, e),
+            }
+        }
+        exit(0);
+    }
+    if let Some(name) = delete {
+        let result = worlds::delete_world(&config.saves_dir, &name);
+        if let Err(e) = result {
+            eprintln!("{:#}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+    if let Some((from, to)) = copy {
+        let result = worlds::copy_world(&config.saves_dir, &from, &to);
+        if let Err(e) = result {
+            eprintln!("{:#}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+    let world_dir = world_dir.unwrap_or_else(|| {
+        if let Err(e) = worlds::check_world_name(&config.world) {
+            fail(&format!("{:#}", e));
+        }
+        config.world_dir(&config.world)
+    });
+    if new || seed.is_some() || generator != DEFAULT_GENERATOR {
+        let seed = seed.unwrap_or_else(|| timestamp() ^ ((std::process::id() as u64) << 32));
+        if let Err(e) = worlds::create_world(&world_dir, seed, &generator) {
+            eprintln!("{:#}", e);
+            exit(1);
+        }
+    }
+    world_dir
+}
@@ -0,0 +1,165 @@
+use crate::block::Block;
+use crate::chunk::{neighborhood_block, Chunk};
+use crate::generator::{position_seed, unit};
+use glam::{ivec3, IVec3};
+use std::sync::Arc;
+
+// distance features reach from their column, above the block they stand on and below it
+const REACH: i32 = 2;
+const HEIGHT: i32 = 7;
+const DEPTH: i32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    Tree,
+    Boulder,
+}
+
+// blocks of the features, by increasing priority: a block of a feature replaces air
+// and the blocks of features before it, so the order the features are placed in
+// does not change the chunk
+pub struct FeatureBlocks {
+    pub leaves: Block,
+    pub log: Block,
+    pub stone: Block,
+}
+impl FeatureBlocks {
+    fn priority(&self, block: Block) -> Option<usize> {
+        if block.block_type == 0 {
+            return Some(0);
+        }
+        [self.leaves, self.log, self.stone]
+            .iter()
+            .position(|feature_block| *feature_block == block)
+            .map(|i| i + 1)
+    }
+}
+
+// what a column can hold: its feature, the chance of having one on each block it can
+// stand on, and that block
+pub struct ColumnFeature {
+    pub feature: Feature,
+    pub chance: f32,
+    pub ground: Block,
+    // y of the only ground block of the column that can hold the feature, when it is known
+    pub surface: Option<i32>,
+}
+
+// the base terrain of a chunk and its neighbors
+struct BaseTerrain<'a> {
+    origin: IVec3,
+    chunks: &'a [Arc<Chunk>; 27],
+}
+impl BaseTerrain<'_> {
+    fn block(&self, pos: IVec3) -> Block {
+        neighborhood_block(self.chunks, pos - self.origin)
+    }
+}
+
+// writes the blocks of the features falling inside the chunk
+struct FeatureWriter<'a> {
+    origin: IVec3,
+    chunk: &'a mut Chunk,
+    blocks: &'a FeatureBlocks,
+}
+impl FeatureWriter<'_> {
+    fn set(&mut self, pos: IVec3, block: Block) {
+        let local = pos - self.origin;
+        if (local.x | local.y | local.z) & !31 != 0 {
+            return;
+        }
+        let local = local.as_uvec3();
+        let current = self.blocks.priority(self.chunk.get_block(local));
+        if current.is_some_and(|current| Some(current) < self.blocks.priority(block)) {
+            self.chunk.set_block(local, block);
+        }
+    }
+    fn tree(&mut self, ground: IVec3, seed: u64) {
+        let height = 4 + (seed % 3) as i32;
+        for dy in height - 2..height + 2 {
+            let radius: i32 = if dy < height { 2 } else { 1 };
+            for dz in -radius..radius + 1 {
+                for dx in -radius..radius + 1 {
+                    // corners are left out at random, always on the top layer
+                    let corner = dx.abs() == radius && dz.abs() == radius;
+                    let bit = ((dy * 5 + dz + 2) * 5 + dx + 2) as u64 % 64;
+                    if corner && (dy == height + 1 || seed >> bit & 1 == 0) {
+                        continue;
+                    }
+                    self.set(ground + ivec3(dx, dy, dz), self.blocks.leaves);
+                }
+            }
+        }
+        for dy in 1..height + 1 {
+            self.set(ground + ivec3(0, dy, 0), self.blocks.log);
+        }
+    }
+    fn boulder(&mut self, ground: IVec3, seed: u64) {
+        let radius = 1 + (seed % 2) as i32;
+        let center = ground + ivec3(0, 1, 0);
+        for dz in -radius..radius + 1 {
+            for dy in -radius..radius + 1 {
+                for dx in -radius..radius + 1 {
+                    if dx * dx + dy * dy + dz * dz < radius * radius + radius {
+                        self.set(center + ivec3(dx, dy, dz), self.blocks.stone);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// places the features of the columns around the chunk at pos that reach into it, they
+// stand on the ground block of their column when it has a non solid block above it
+// and it is the surface of the column,
+// features only depend on the seed and the base terrain so the chunks they cross agree
+#[profiling::function]
+pub fn decorate(
+    seed: u64,
+    pos: IVec3,
+    base: &[Arc<Chunk>; 27],
+    chunk: &mut Chunk,
+    blocks: &FeatureBlocks,
+    is_solid: &dyn Fn(Block) -> bool,
+    column_feature: &dyn Fn(i32, i32) -> Option<ColumnFeature>,
+) {
+    let origin = pos * 32;
+    let terrain = BaseTerrain {
+        origin,
+        chunks: base,
+    };
+    let mut writer = FeatureWriter {
+        origin,
+        chunk,
+        blocks,
+    };
+    for z in origin.z - REACH..origin.z + 32 + REACH {
+        for x in origin.x - REACH..origin.x + 32 + REACH {
+            let column = match column_feature(x, z) {
+                Some(column) => column,
+                None => continue,
+            };
+            let (bottom, top) = (origin.y - HEIGHT, origin.y + 32 + DEPTH);
+            let ys = match column.surface {
+                Some(surface) => surface.max(bottom)..(surface + 1).min(top),
+                None => bottom..top,
+            };
+            for y in ys {
+                let ground = ivec3(x, y, z);
+                if terrain.block(ground) != column.ground
+                    || is_solid(terrain.block(ground + ivec3(0, 1, 0)))
+                {
+                    continue;
+                }
+                let seed = position_seed(seed, ground);
+                if unit(seed) >= column.chance {
+                    continue;
+                }
+                match column.feature {
+                    Feature::Tree => writer.tree(ground, seed),
+                    Feature::Boulder => writer.boulder(ground, seed),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use crate::biome::{self, BiomeDefinition, BIOMES};
+use crate::block::Block;
+use crate::chunk::Chunk;
+use crate::decoration::{self, ColumnFeature, FeatureBlocks};
+use crate::geology::{self, ORES, STRATA};
+use crate::registry::BlockRegistry;
+use anyhow::{bail, Context, Result};
+use glam::{uvec3, vec3, IVec3, Vec3};
+use simdnoise::NoiseBuilder;
+use std::sync::Arc;
+
+// name and version of the generators worlds can be created with
+pub const GENERATORS: [(&str, u32); 2] = [
+    (NoiseGenerator::NAME, NoiseGenerator::VERSION),
+    (FlatGenerator::NAME, FlatGenerator::VERSION),
+];
+pub const DEFAULT_GENERATOR: &str = NoiseGenerator::NAME;
+
+// writes the terrain of the chunks, each world is generated by the one it was created with
+pub trait WorldGenerator: Send + Sync {
+    // identifies the terrain in the world metadata and the region headers
+    fn name(&self) -> &str;
+    // raised when the same seed gives another terrain
+    fn version(&self) -> u32;
+    // the chunk is empty, it is compacted after
+    fn generate(&self, pos: IVec3, chunk: &mut Chunk);
+    // adds the features reaching into the chunk, like trees, once the base terrain of its
+    // neighbors is generated, base holds it in the order of chunk::neighbor_index
+    fn decorate(
+        &self,
+        _pos: IVec3,
+        _base: &[Arc<Chunk>; 27],
+        _chunk: &mut Chunk,
+        _registry: &BlockRegistry,
+    ) {
+    }
+    // biome of the column, None when the generator has no biomes
+    fn biome(&self, _x: i32, _z: i32) -> Option<&'static BiomeDefinition> {
+        None
+    }
+}
+
+// splitmix64 of the world seed and the layer, every noise layer gets its own seed
+pub fn layer_seed(seed: u64, layer: u64) -> u64 {
+    let mut z = seed.wrapping_add(layer.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+// seed of what stands at pos, like a feature on the block or an ore in the chunk
+pub fn position_seed(seed: u64, pos: IVec3) -> u64 {
+    let column = layer_seed(seed, pos.x as u32 as u64 | (pos.z as u32 as u64) << 32);
+    layer_seed(column, pos.y as u32 as u64)
+}
+// from 0 to 1
+pub fn unit(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+// the gradient noise stays between -NOISE_RANGE and NOISE_RANGE
+const NOISE_RANGE: f32 = 0.022;
+// noise of a chunk at its position with a frequency and a seed
+type NoiseFn = fn(Vec3, f32, i32) -> Vec<f32>;
+
+// the noise is computed without simd, whose results depend on the cpu,
+// so a seed gives the same terrain on every machine
+fn noise_2d(pos: Vec3, freq: f32, seed: i32) -> Vec<f32> {
+    let noise = NoiseBuilder::gradient_2d_offset(pos.x, 32, pos.z, 32)
+        .with_freq(freq)
+        .with_seed(seed)
+        .wrap();
+    unsafe { simdnoise::scalar::get_2d_noise(&noise).0 }
+}
+fn noise_3d(pos: Vec3, freq: f32, seed: i32) -> Vec<f32> {
+    let noise = NoiseBuilder::gradient_3d_offset(pos.x, 32, pos.y, 32, pos.z, 32)
+        .with_freq(freq)
+        .with_seed(seed)
+        .wrap();
+    unsafe { simdnoise::scalar::get_3d_noise(&noise).0 }
+}
+// the simd noise of version 1 of the noise generator, kept for the worlds created with it
+fn legacy_noise_2d(pos: Vec3, freq: f32, seed: i32) -> Vec<f32> {
+    NoiseBuilder::gradient_2d_offset(pos.x, 32, pos.z, 32)
+        .with_freq(freq)
+        .with_seed(seed)
+        .generate()
+        .0
+}
+fn legacy_noise_3d(pos: Vec3, freq: f32, seed: i32) -> Vec<f32> {
+    NoiseBuilder::gradient_3d_offset(pos.x, 32, pos.y, 32, pos.z, 32)
+        .with_freq(freq)
+        .with_seed(seed)
+        .generate()
+        .0
+}
+
+pub fn generator_version(name: &str) -> Option<u32> {
+    GENERATORS
+        .iter()
+        .find(|generator| generator.0 == name)
+        .map(|generator| generator.1)
+}
+// the generator at the version the world was created with, so the chunks generated
+// later join the saved ones
+pub fn create_generator(
+    name: &str,
+    version: u32,
+    seed: u64,
+    registry: &BlockRegistry,
+) -> Result<Box<dyn WorldGenerator>> {
+    let latest = match generator_version(name) {
+        Some(latest) => latest,
+        None => bail!("unknown generator \"{}\"", name),
+    };
+    if version == 0 || version > latest {
+        bail!(
+            "the world was created with generator {} v{}, this build only knows up to v{}",
+            name,
+            version,
+            latest
+        );
+    }
+    // the blocks are resolved once here, a missing one would turn into air
+    let context = || format!("generator {} v{} can't be used", name, version);
+    let generator: Box<dyn WorldGenerator> = match name {
+        NoiseGenerator::NAME => {
+            Box::new(NoiseGenerator::new(seed, version, registry).with_context(context)?)
+        }
+        _ => Box::new(FlatGenerator::new(registry).with_context(context)?),
+    };
+    Ok(generator)
+}
+// a new chunk with the base terrain of the generator and its heightmap
+#[profiling::function]
+pub fn generate_chunk(
+    generator: &dyn WorldGenerator,
+    pos: IVec3,
+    registry: &BlockRegistry,
+) -> Chunk {
+    let mut chunk = Chunk::new();
+    generator.generate(pos, &mut chunk);
+    chunk.compact();
+    chunk.compute_heightmap(registry);
+    chunk
+}
+// the chunk at the center of base with its features
+#[profiling::function]
+pub fn decorate_chunk(
+    generator: &dyn WorldGenerator,
+    pos: IVec3,
+    base: &[Arc<Chunk>; 27],
+    registry: &BlockRegistry,
+) -> Chunk {
+    let mut chunk = (*base[13]).clone();
+    generator.decorate(pos, base, &mut chunk, registry);
+    chunk.compact();
+    chunk.compute_heightmap(registry);
+    chunk
+}
+
+// hills cut by valleys and caves, shaped by the biomes, over strata holding ores,
+// each version keeps the terrain of the previous ones:
+// v1 simd noise seeded with the halves of the world seed
+// v2 scalar noise seeded with layer_seed
+// v3 biomes
+// v4 trees and boulders
+// v5 strata and ores
+// v6 features only on the surface, not on the ground of the caves
+pub struct NoiseGenerator {
+    version: u32,
+    // seeds of the hills, valleys, the two cave layers, temperature and humidity
+    seeds: [i32; 6],
+    decoration_seed: u64,
+    strata_seed: i32,
+    ore_seed: u64,
+    strata_blocks: Vec<Block>,
+    ore_blocks: Vec<Block>,
+    feature_blocks: FeatureBlocks,
+    // surface and filler blocks of each biome
+    biome_blocks: Vec<(Block, Block)>,
+}
+impl NoiseGenerator {
+    pub const NAME: &'static str = "noise";
+    pub const VERSION: u32 = 6;
+    pub fn new(seed: u64, version: u32, registry: &BlockRegistry) -> Result<Self> {
+        let seeds = if version == 1 {
+            let (low, high) = (seed as i32, (seed >> 32) as i32);
+            [low, high, low, high, 0, 0]
+        } else {
+            std::array::from_fn(|layer| layer_seed(seed, layer as u64) as i32)
+        };
+        Ok(NoiseGenerator {
+            version,
+            seeds,
+            decoration_seed: layer_seed(seed, 6),
+            strata_seed: layer_seed(seed, 7) as i32,
+            ore_seed: layer_seed(seed, 8),
+            strata_blocks: STRATA
+                .iter()
+                .map(|stratum| registry.defined_block(stratum.block))
+                .collect::<Result<_>>()?,
+            ore_blocks: ORES
+                .iter()
+                .map(|ore| registry.defined_block(ore.block))
+                .collect::<Result<_>>()?,
+            feature_blocks: FeatureBlocks {
+                leaves: registry.defined_block("leaves")?,
+                log: registry.defined_block("log")?,
+                stone: registry.defined_block("stone")?,
+            },
+            biome_blocks: BIOMES
+                .iter()
+                .map(|biome| {
+                    Ok((
+                        registry.defined_block(biome.surface)?,
+                        registry.defined_block(biome.filler)?,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+    // temperature and humidity of the columns of the chunk at x + z * 32, from -1 to 1
+    fn climate(&self, chunk_pos: Vec3) -> (Vec<f32>, Vec<f32>) {
+        let scale = |noise: Vec<f32>| {
+            noise
+                .into_iter()
+                .map(|value| (value / NOISE_RANGE).clamp(-1.0, 1.0))
+                .collect()
+        };
+        (
+            scale(noise_2d(chunk_pos, 0.0008, self.seeds[4])),
+            scale(noise_2d(chunk_pos, 0.0008, self.seeds[5])),
+        )
+    }
+    // height, filler depth and biome of each column of the chunk at x + z * 32, the first two
+    // blended between biomes, the versions without biomes are all plains
+    fn columns(&self, chunk_pos: Vec3, noise_2d: NoiseFn) -> Vec<(f32, f32, usize)> {
+        let hills = noise_2d(chunk_pos, 0.002, self.seeds[0]);
+        let valley = noise_2d(chunk_pos, 0.01, self.seeds[1]);
+        if self.version < 3 {
+            return (0..32 * 32)
+                .map(|i| (hills[i] * 2000.0 - valley[i].abs() * 500.0, 5.0, 0))
+                .collect();
+        }
+        let (temperature, humidity) = self.climate(chunk_pos);
+        (0..32 * 32)
+            .map(|i| {
+                let weights = biome::biome_weights(temperature[i], humidity[i]);
+                let mut amplitude = 0.0;
+                let mut filler_depth = 0.0;
+                for (weight, biome) in weights.iter().zip(BIOMES.iter()) {
+                    amplitude += weight * biome.amplitude;
+                    filler_depth += weight * biome.filler_depth;
+                }
+                let height = hills[i] * 2000.0 * amplitude - valley[i].abs() * 500.0;
+                let biome = biome::nearest_biome(temperature[i], humidity[i]);
+                (height, filler_depth, biome)
+            })
+            .collect()
+    }
+}
+impl WorldGenerator for NoiseGenerator {
+    fn name(&self) -> &str {
+        NoiseGenerator::NAME
+    }
+    fn version(&self) -> u32 {
+        self.version
+    }
+    #[profiling::function]
+    fn generate(&self, pos: IVec3, chunk: &mut Chunk) {
+        let chunk_pos: Vec3 = vec3(
+            (pos.x * 32) as f32,
+            (pos.y * 32) as f32,
+            (pos.z * 32) as f32,
+        );
+        let (noise_2d, noise_3d): (NoiseFn, NoiseFn) = if self.version == 1 {
+            (legacy_noise_2d, legacy_noise_3d)
+        } else {
+            (noise_2d, noise_3d)
+        };
+        let caves1 = noise_3d(chunk_pos, 0.007, self.seeds[2]);
+        let caves2 = noise_3d(chunk_pos, 0.01, self.seeds[3]);
+        let strata = if self.version >= 5 {
+            noise_2d(chunk_pos, 0.02, self.strata_seed)
+        } else {
+            vec![]
+        };
+        let columns = self.columns(chunk_pos, noise_2d);
+        let mut underground = false;
+        for z in 0usize..32 {
+            for y in 0usize..32 {
+                for x in 0usize..32 {
+                    let (height, filler_depth, biome) = columns[x + (z << 5)];
+                    let depth = height - (pos.y * 32 + y as i32) as f32;
+                    let mut block = Block::new(0);
+                    let id = x + (y << 5) + (z << 10);
+
+                    if caves1[id].abs() + caves2[id].abs() > 0.005 {
+                        if depth > filler_depth {
+                            let stratum = if self.version >= 5 {
+                                let waviness = strata[x + (z << 5)] / NOISE_RANGE;
+                                geology::stratum(depth + waviness * geology::STRATA_WAVINESS)
+                            } else {
+                                0
+                            };
+                            block = self.strata_blocks[stratum];
+                            underground = true;
+                        } else if depth > 1.0 {
+                            block = self.biome_blocks[biome].1;
+                        } else if depth > 0.0 {
+                            block = self.biome_blocks[biome].0;
+                        }
+                    }
+                    chunk.set_block(uvec3(x as u32, y as u32, z as u32), block);
+                }
+            }
+        }
+        if !underground || self.version < 5 {
+            return;
+        }
+        // ores only replace the strata, so the caves stay open
+        geology::place_ores(self.ore_seed, pos, &mut |ore, block_pos| {
+            let local = (block_pos - pos * 32).as_uvec3();
+            let depth = columns[(local.x + (local.z << 5)) as usize].0 - block_pos.y as f32;
+            if depth >= ORES[ore].min_depth
+                && depth < ORES[ore].max_depth
+                && self.strata_blocks.contains(&chunk.get_block(local))
+            {
+                chunk.set_block(local, self.ore_blocks[ore]);
+            }
+        });
+    }
+    fn decorate(
+        &self,
+        pos: IVec3,
+        base: &[Arc<Chunk>; 27],
+        chunk: &mut Chunk,
+        registry: &BlockRegistry,
+    ) {
+        if self.version < 4 {
+            return;
+        }
+        // columns of the chunk and its horizontal neighbors
+        let columns: Vec<Vec<(f32, f32, usize)>> = (0..9)
+            .map(|i| {
+                let chunk_pos = vec3(
+                    ((pos.x + i % 3 - 1) * 32) as f32,
+                    0.0,
+                    ((pos.z + i / 3 - 1) * 32) as f32,
+                );
+                self.columns(chunk_pos, noise_2d)
+            })
+            .collect();
+        let column_feature = |x: i32, z: i32| {
+            let chunk = ((x >> 5) - pos.x + 1) + ((z >> 5) - pos.z + 1) * 3;
+            let (height, _, biome) = columns[chunk as usize][((x & 31) + ((z & 31) << 5)) as usize];
+            Some(ColumnFeature {
+                feature: BIOMES[biome].feature,
+                chance: BIOMES[biome].decoration,
+                ground: self.biome_blocks[biome].0,
+                // the surface block is the one less than a block under the height,
+                // the ground blocks of the caves below it were decorated before version 6
+                surface: (self.version >= 6).then(|| height.ceil() as i32 - 1),
+            })
+        };
+        decoration::decorate(
+            self.decoration_seed,
+            pos,
+            base,
+            chunk,
+            &self.feature_blocks,
+            &|block| registry.is_solid(block),
+            &column_feature,
+        );
+    }
+    fn biome(&self, x: i32, z: i32) -> Option<&'static BiomeDefinition> {
+        if self.version < 3 {
+            return None;
+        }
+        let (temperature, humidity) = self.climate(vec3((x & !31) as f32, 0.0, (z & !31) as f32));
+        let i = ((x & 31) + ((z & 31) << 5)) as usize;
+        Some(&BIOMES[biome::nearest_biome(temperature[i], humidity[i])])
+    }
+}
+
+// grass at y = -1 over three layers of dirt and stone below, the same for every seed
+pub struct FlatGenerator {
+    grass: Block,
+    dirt: Block,
+    stone: Block,
+}
+impl FlatGenerator {
+    pub const NAME: &'static str = "flat";
+    pub const VERSION: u32 = 1;
+    pub fn new(registry: &BlockRegistry) -> Result<Self> {
+        Ok(FlatGenerator {
+            grass: registry.defined_block("grass")?,
+            dirt: registry.defined_block("dirt")?,
+            stone: registry.defined_block("stone")?,
+        })
+    }
+}
+impl WorldGenerator for FlatGenerator {
+    fn name(&self) -> &str {
+        FlatGenerator::NAME
+    }
+    fn version(&self) -> u32 {
+        FlatGenerator::VERSION
+    }
+    fn generate(&self, pos: IVec3, chunk: &mut Chunk) {
+        for y in 0..32 {
+            let block = match pos.y * 32 + y as i32 {
+                -1 => self.grass,
+                -4..=-2 => self.dirt,
+                y if y < -4 => self.stone,
+                _ => continue,
+            };
+            for z in 0..32 {
+                for x in 0..32 {
+                    chunk.set_block(uvec3(x, y, z), block);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::chunk::neighborhood;
+use crate::generator::{layer_seed, position_seed, unit};
+use glam::{ivec3, vec3, IVec3};
+
+// the strata and ores are compiled in like the biomes instead of read from a file like
+// blocks.ron, they are part of the terrain of a version of the noise generator: the chunks
+// of a world that were not saved yet are generated with them, so editing them would change
+// existing worlds and leave seams next to the saved chunks, changes need a new version
+
+// layers of rock under the filler of the biomes, each from its depth under the surface
+// down to the next one
+pub struct StratumDefinition {
+    pub block: &'static str,
+    pub depth: f32,
+}
+
+pub const STRATA: [StratumDefinition; 3] = [
+    StratumDefinition {
+        block: "stone",
+        depth: 0.0,
+    },
+    StratumDefinition {
+        block: "slate",
+        depth: 48.0,
+    },
+    StratumDefinition {
+        block: "basalt",
+        depth: 128.0,
+    },
+];
+// the borders between the strata move up and down by up to this
+pub const STRATA_WAVINESS: f32 = 8.0;
+
+pub fn stratum(depth: f32) -> usize {
+    STRATA
+        .iter()
+        .rposition(|stratum| stratum.depth <= depth)
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OreShape {
+    // a rough ball of radius size
+    Blob,
+    // a thin line of length size
+    Vein,
+}
+
+// ores replace the blocks of the strata between min_depth and max_depth under the surface,
+// frequency is the mean number of them starting in a chunk, size is below 32 so they
+// only reach into the neighbors of that chunk
+pub struct OreDefinition {
+    pub block: &'static str,
+    pub shape: OreShape,
+    pub frequency: f32,
+    pub size: i32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+pub const ORES: [OreDefinition; 3] = [
+    OreDefinition {
+        block: "coal_ore",
+        shape: OreShape::Blob,
+        frequency: 12.0,
+        size: 2,
+        min_depth: 6.0,
+        max_depth: 160.0,
+    },
+    OreDefinition {
+        block: "iron_ore",
+        shape: OreShape::Blob,
+        frequency: 6.0,
+        size: 2,
+        min_depth: 24.0,
+        max_depth: f32::MAX,
+    },
+    OreDefinition {
+        block: "gold_ore",
+        shape: OreShape::Vein,
+        frequency: 1.5,
+        size: 16,
+        min_depth: 96.0,
+        max_depth: f32::MAX,
+    },
+];
+
+// calls place with the index of the ore and the position of each block of the ores
+// reaching into the chunk at pos, ores only depend on the seed so the chunks they
+// cross agree
+#[profiling::function]
+pub fn place_ores(seed: u64, pos: IVec3, place: &mut dyn FnMut(usize, IVec3)) {
+    let origin = pos * 32;
+    let mut place_inside = |ore: usize, block_pos: IVec3| {
+        let local = block_pos - origin;
+        if (local.x | local.y | local.z) & !31 == 0 {
+            place(ore, block_pos);
+        }
+    };
+    for chunk_pos in neighborhood(pos) {
+        let chunk_seed = position_seed(seed, chunk_pos);
+        for (index, ore) in ORES.iter().enumerate() {
+            let ore_seed = layer_seed(chunk_seed, index as u64);
+            let count = ore.frequency as u64 + (unit(ore_seed) < ore.frequency.fract()) as u64;
+            for n in 0..count {
+                let seed = layer_seed(ore_seed, n + 1);
+                let start = chunk_pos * 32
+                    + ivec3(
+                        (seed & 31) as i32,
+                        (seed >> 5 & 31) as i32,
+                        (seed >> 10 & 31) as i32,
+                    );
+                // skip the ores that can't reach the chunk
+                let distance = (start - origin).max(origin + 31 - start).max_element() - 31;
+                if distance > ore.size {
+                    continue;
+                }
+                match ore.shape {
+                    OreShape::Blob => {
+                        blob(start, ore.size, seed, &mut |pos| place_inside(index, pos))
+                    }
+                    OreShape::Vein => {
+                        vein(start, ore.size, seed, &mut |pos| place_inside(index, pos))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn blob(center: IVec3, radius: i32, seed: u64, place: &mut dyn FnMut(IVec3)) {
+    let mut i = 0;
+    for dz in -radius..radius + 1 {
+        for dy in -radius..radius + 1 {
+            for dx in -radius..radius + 1 {
+                // the border is left out at random
+                let border = 0.4 + 0.6 * unit(layer_seed(seed, i));
+                i += 1;
+                if ((dx * dx + dy * dy + dz * dz) as f32) <= (radius * radius) as f32 * border {
+                    place(center + ivec3(dx, dy, dz));
+                }
+            }
+        }
+    }
+}
+
+fn vein(center: IVec3, length: i32, seed: u64, place: &mut dyn FnMut(IVec3)) {
+    let yaw = unit(seed) * std::f32::consts::TAU;
+    let pitch = (unit(layer_seed(seed, 0)) - 0.5) * 1.2;
+    let direction = vec3(
+        yaw.cos() * pitch.cos(),
+        pitch.sin(),
+        yaw.sin() * pitch.cos(),
+    );
+    let start = center.as_vec3() + 0.5 - direction * (length as f32 / 2.0);
+    const SIDES: [IVec3; 6] = [
+        ivec3(-1, 0, 0),
+        ivec3(1, 0, 0),
+        ivec3(0, -1, 0),
+        ivec3(0, 1, 0),
+        ivec3(0, 0, -1),
+        ivec3(0, 0, 1),
+    ];
+    for step in 0..length {
+        let pos = (start + direction * step as f32).floor().as_ivec3();
+        place(pos);
+        // thickened on a random side
+        place(pos + SIDES[(layer_seed(seed, step as u64 + 1) % 6) as usize]);
+    }
+}
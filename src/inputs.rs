@@ -9,6 +9,8 @@ pub struct Inputs {
     pub mouse_motion_x: f64,
     pub mouse_motion_y: f64,
     pub mouse_button_states: [bool; 3],
+    // keys pressed since the last reset, without repeats
+    pub key_presses: Vec<KeyCode>,
     cur_lock: bool,
 }
 impl Inputs {
@@ -20,12 +22,14 @@ impl Inputs {
             mouse_motion_x: 0.0,
             mouse_motion_y: 0.0,
             mouse_button_states: [false, false, false],
+            key_presses: vec![],
             cur_lock: false,
         }
     }
     pub fn reset(&mut self) {
         self.mouse_motion_x = 0.0;
         self.mouse_motion_y = 0.0;
+        self.key_presses.clear();
     }
     pub fn update(&mut self, event: &Event<()>, window: &Window) -> bool {
         match event {
@@ -46,6 +50,7 @@ impl Inputs {
                         KeyEvent {
                             physical_key: PhysicalKey::Code(key),
                             state,
+                            repeat,
                             ..
                         },
                     ..
@@ -67,6 +72,9 @@ impl Inputs {
                         }
                         _ => {}
                     }
+                    if *state == ElementState::Pressed && !repeat {
+                        self.key_presses.push(*key);
+                    }
                     self.keyboard[*key as usize] = *state == ElementState::Pressed;
                     true
                 }
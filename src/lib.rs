@@ -0,0 +1,17 @@
+#![feature(hash_extract_if)]
+
+pub mod biome;
+pub mod block;
+pub mod chunk;
+pub mod chunk_map;
+pub mod decoration;
+pub mod direction;
+pub mod generator;
+pub mod geology;
+pub mod mesher;
+pub mod region;
+pub mod registry;
+pub mod settings;
+pub mod vox;
+pub mod world_meta;
+pub mod worlds;
@@ -1,5 +1,3 @@
-#![feature(hash_extract_if)]
-
 use crate::camera::Camera;
 use crate::inputs::Inputs;
 use crate::renderer::Renderer;
@@ -9,18 +7,21 @@ use profiling::tracy_client;
 use winit::keyboard::{Key, KeyCode, NamedKey};
 use winit::{event::*, event_loop::EventLoop, window::WindowBuilder};
 
-mod block;
+use rust_voxel_engine::{
+    biome, block, chunk, chunk_map, direction, generator, mesher, region, registry, settings, vox,
+    world_meta,
+};
+
 mod camera;
-mod chunk;
 mod chunk_loader;
-mod chunk_map;
 mod chunk_renderer;
+mod cli;
 mod inputs;
 mod mesh;
 mod mipmap;
-mod region;
 mod render_region;
 mod renderer;
+mod storage;
 mod texture;
 mod util;
 mod world;
@@ -29,14 +30,21 @@ fn main() {
     env_logger::init();
     #[cfg(feature = "profile-with-tracy")]
     tracy_client::Client::start();
+    let save_dir = cli::select_world();
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
     use futures::executor::block_on;
 
-    let mut renderer = block_on(Renderer::new(&window));
-    let mut camera = Camera::new(0.1);
+    let mut world = match World::new(save_dir) {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut renderer = block_on(Renderer::new(&window, world.registry.textures()));
+    let mut camera = Camera::new(&world.meta.player);
     let mut inputs = Inputs::new();
-    let mut world = World::new();
     camera.update(&inputs, &mut world);
     let mut counter: i32 = 0;
     event_loop
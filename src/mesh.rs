@@ -1,9 +1,3 @@
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Face {
-    pub pos_dir_tex: u32,
-    pub light: [u8; 4],
-}
 pub struct Mesh {
     pub storage_buffer: wgpu::Buffer,
     pub bind_group: Option<wgpu::BindGroup>,
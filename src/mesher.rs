@@ -0,0 +1,503 @@
+use crate::block::{Block, Property};
+use crate::chunk::{neighborhood_block, Chunk};
+use crate::direction::Direction;
+use crate::registry::{BlockRegistry, Opacity};
+use glam::{ivec3, uvec3, IVec3, UVec3};
+use std::sync::Arc;
+
+// for each axis, the face of an upright block shown in each direction
+// and whether its texture is turned to follow the axis
+const AXIS_FACES: [[usize; 6]; 3] = [[0, 1, 2, 3, 4, 5], [4, 5, 2, 3, 0, 1], [0, 1, 4, 5, 2, 3]];
+const AXIS_ROTATED: [[bool; 6]; 3] = [
+    [false; 6],
+    [false, false, true, true, false, false],
+    [true, true, false, false, true, true],
+];
+const FACES_LIGHT: [f32; 6] = [0.4, 0.4, 0.7, 0.7, 0.1, 1.0];
+// blocks around a face darkening its corners, turned by Direction::transform
+const AO_OFFSETS: [IVec3; 8] = [
+    ivec3(1, 1, 0),
+    ivec3(1, 1, 1),
+    ivec3(0, 1, 1),
+    ivec3(-1, 1, 1),
+    ivec3(-1, 1, 0),
+    ivec3(-1, 1, -1),
+    ivec3(0, 1, -1),
+    ivec3(1, 1, -1),
+];
+// axes of the faces of each direction along the x and y of their texture
+const FACE_AXES: [(usize, usize); 6] = [(2, 1), (2, 1), (0, 1), (0, 1), (2, 0), (2, 0)];
+// the bitmask mesher works on the chunk and a one cell border
+const MAX_SIZE: i32 = 34;
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Face {
+    pub pos_dir_tex: u32,
+    pub light: [u8; 4],
+    // width - 1 and height - 1 of the quad on 5 bits each, along the axes of the texture
+    pub size: u32,
+}
+// faces of a chunk, the opaque and cutout ones by direction
+#[derive(PartialEq, Eq, Debug)]
+pub struct ChunkMesh {
+    pub faces: [Vec<Face>; 6],
+    pub translucent: Vec<Face>,
+}
+impl ChunkMesh {
+    fn new() -> Self {
+        ChunkMesh {
+            faces: Default::default(),
+            translucent: vec![],
+        }
+    }
+}
+// distant chunks are meshed from cells of 2^level blocks, each shown as its most common block
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Lod {
+    pub level: u32,
+    // sides toward -x, +x, -z and +z whose neighbor has another level, the chunk draws
+    // the faces of its cells along them as skirts over the gaps between the levels
+    pub seams: [bool; 4],
+}
+
+// the texture id uses 10 bits, the 11th turns the texture by a quarter
+fn face_texture(registry: &BlockRegistry, block: Block, dir: usize) -> u32 {
+    let axis = if registry.get(block).properties.contains(&Property::Axis) {
+        (block.get(Property::Axis) as usize).min(2)
+    } else {
+        0
+    };
+    registry.face_texture(block, AXIS_FACES[axis][dir]) | (AXIS_ROTATED[axis][dir] as u32) << 10
+}
+// ao tells which of the blocks at AO_OFFSETS are opaque
+fn create_face(
+    registry: &BlockRegistry,
+    pos: UVec3,
+    dir: &Direction,
+    block: Block,
+    ao: [bool; 8],
+) -> Face {
+    let light: [u8; 4] = [
+        ((255 - (ao[4] || ao[5] || ao[6]) as u32 * 180) as f32 * FACES_LIGHT[dir.id as usize])
+            as u8,
+        ((255 - (ao[2] || ao[3] || ao[4]) as u32 * 180) as f32 * FACES_LIGHT[dir.id as usize])
+            as u8,
+        ((255 - (ao[0] || ao[1] || ao[2]) as u32 * 180) as f32 * FACES_LIGHT[dir.id as usize])
+            as u8,
+        ((255 - (ao[6] || ao[7] || ao[0]) as u32 * 180) as f32 * FACES_LIGHT[dir.id as usize])
+            as u8,
+    ];
+    let texture = face_texture(registry, block, dir.id as usize);
+    Face {
+        pos_dir_tex: (pos.x & 63)
+            | ((pos.y & 63) << 6)
+            | ((pos.z & 63) << 12)
+            | ((dir.id as u32 & 7) << 18)
+            | ((texture & 2047) << 21),
+        light,
+        size: 0,
+    }
+}
+
+// chunks holds the meshed chunk and its neighbors in the order of chunk::neighbor_index,
+// faces on the border with the neighbors of higher coordinates belong to the meshed chunk
+pub fn create_mesh(chunks: &[Arc<Chunk>; 27], registry: &BlockRegistry) -> ChunkMesh {
+    create_lod_mesh(chunks, registry, Lod::default())
+}
+#[profiling::function]
+pub fn create_lod_mesh(chunks: &[Arc<Chunk>; 27], registry: &BlockRegistry, lod: Lod) -> ChunkMesh {
+    let neighborhood = Neighborhood::new(chunks, registry, lod.level);
+    let cells = neighborhood.cells;
+    let inside = ((1u64 << cells) - 1) << 1;
+    let mut mesh = ChunkMesh::new();
+    // translucent faces of all directions are sorted in the order of create_mesh_per_block
+    let mut translucent = vec![];
+    for y in 0..cells {
+        for z in 0..cells {
+            let row = neighborhood.row(y, z);
+            // neighbors along x are in the same row, shifted by one bit
+            let neighbor_rows = [row, neighborhood.row(y, z + 1), neighborhood.row(y + 1, z)];
+            let shifts = [1, 0, 0];
+            for axis in 0..3 {
+                let dir = axis as u8 * 2 + 1;
+                let neighbor_row = neighbor_rows[axis];
+                let shift = shifts[axis];
+                // bit x + 1 is set when the face of the block at x toward its neighbor is visible,
+                // and in negative when the face of the neighbor toward the block is visible
+                let mut positive = neighborhood.visible[row]
+                    & !(neighborhood.opaque[neighbor_row] >> shift)
+                    & inside;
+                let mut negative = (neighborhood.visible[neighbor_row] >> shift)
+                    & !neighborhood.opaque[row]
+                    & inside;
+                // translucent blocks next to blocks of the same type
+                let mut pairs = neighborhood.translucent[row]
+                    & (neighborhood.translucent[neighbor_row] >> shift)
+                    & inside;
+                while pairs != 0 {
+                    let x = pairs.trailing_zeros() as i32 - 1;
+                    pairs &= pairs - 1;
+                    let pos = ivec3(x, y, z);
+                    let neighbor = pos + Direction { id: dir }.get_norm();
+                    if neighborhood.block(pos).block_type == neighborhood.block(neighbor).block_type
+                    {
+                        positive &= !(1 << (x + 1));
+                        negative &= !(1 << (x + 1));
+                    }
+                }
+                // the neighbor of another level draws its own skirt
+                let seam = match axis {
+                    0 if lod.seams[1] => 1 << cells,
+                    1 if lod.seams[3] && z == cells - 1 => inside,
+                    _ => 0,
+                };
+                positive |= neighborhood.visible[row] & seam;
+                negative &= !seam;
+                let origin = ivec3(0, y, z);
+                neighborhood.add_faces(
+                    registry,
+                    &mut mesh,
+                    &mut translucent,
+                    positive,
+                    origin,
+                    dir,
+                );
+                neighborhood.add_faces(
+                    registry,
+                    &mut mesh,
+                    &mut translucent,
+                    negative,
+                    origin,
+                    dir - 1,
+                );
+            }
+            // skirts toward the negative sides, added as faces of the cells before the chunk
+            if lod.seams[0] {
+                let mask = neighborhood.visible[row] >> 1 & 1;
+                let origin = ivec3(0, y, z);
+                neighborhood.add_faces(registry, &mut mesh, &mut translucent, mask, origin, 0);
+            }
+            if lod.seams[2] && z == 0 {
+                let mask = neighborhood.visible[row] & inside;
+                let origin = ivec3(0, y, -1);
+                neighborhood.add_faces(registry, &mut mesh, &mut translucent, mask, origin, 2);
+            }
+        }
+    }
+    translucent.sort_unstable_by_key(|face: &(u32, Face)| face.0);
+    mesh.translucent = translucent.into_iter().map(|face| face.1).collect();
+    mesh
+}
+
+// merges the adjacent faces of each layer sharing their texture into quads, only faces with
+// the same light on their four corners are merged so the ambient occlusion looks the same,
+// translucent faces are left alone to be sorted one by one
+#[profiling::function]
+pub fn merge_faces(mesh: &mut ChunkMesh) {
+    // faces at layer * 1024 + v * 32 + u
+    let mut grid: Vec<Option<Face>> = vec![None; 33 * 32 * 32];
+    for (dir, faces) in mesh.faces.iter_mut().enumerate() {
+        let (u_axis, v_axis) = FACE_AXES[dir];
+        let normal_axis = 3 - u_axis - v_axis;
+        let mut merged = vec![];
+        for face in faces.drain(..) {
+            let pos = [
+                face.pos_dir_tex & 63,
+                face.pos_dir_tex >> 6 & 63,
+                face.pos_dir_tex >> 12 & 63,
+            ];
+            if face.light.iter().all(|light| *light == face.light[0]) {
+                grid[(pos[normal_axis] * 1024 + pos[v_axis] * 32 + pos[u_axis]) as usize] =
+                    Some(face);
+            } else {
+                merged.push(face);
+            }
+        }
+        for layer in 0..33 {
+            for v in 0..32 {
+                for u in 0..32 {
+                    let face = match grid[layer * 1024 + v * 32 + u] {
+                        Some(face) => face,
+                        None => continue,
+                    };
+                    // same texture, rotation and light
+                    let same = |other: Option<Face>| {
+                        other.is_some_and(|other| {
+                            other.pos_dir_tex >> 21 == face.pos_dir_tex >> 21
+                                && other.light == face.light
+                        })
+                    };
+                    let row = layer * 1024 + v * 32;
+                    let mut width = 1;
+                    while u + width < 32 && same(grid[row + u + width]) {
+                        width += 1;
+                    }
+                    let mut height = 1;
+                    while v + height < 32
+                        && (u..u + width).all(|u| same(grid[row + height * 32 + u]))
+                    {
+                        height += 1;
+                    }
+                    for v in 0..height {
+                        for u in u..u + width {
+                            grid[row + v * 32 + u] = None;
+                        }
+                    }
+                    merged.push(Face {
+                        size: (width as u32 - 1) | (height as u32 - 1) << 5,
+                        ..face
+                    });
+                }
+            }
+        }
+        *faces = merged;
+    }
+}
+
+// cells of a chunk and its border with bitmasks of their opacity,
+// each row holds the cells along x, bit x + 1 being the cell at x
+struct Neighborhood {
+    // cells along each axis of the chunk and blocks along each axis of a cell
+    cells: i32,
+    scale: i32,
+    // packed blocks at x + 1 + (y + 1) * size + (z + 1) * size * size, size being cells + 2
+    blocks: Vec<u32>,
+    // rows at y + 1 + (z + 1) * size
+    opaque: Vec<u64>,
+    visible: Vec<u64>,
+    translucent: Vec<u64>,
+}
+impl Neighborhood {
+    #[profiling::function]
+    fn new(chunks: &[Arc<Chunk>; 27], registry: &BlockRegistry, level: u32) -> Self {
+        let cells = 32 >> level;
+        let size = (cells + 2) as usize;
+        let mut neighborhood = Neighborhood {
+            cells,
+            scale: 1 << level,
+            blocks: vec![0; size * size * size],
+            opaque: vec![0; size * size],
+            visible: vec![0; size * size],
+            translucent: vec![0; size * size],
+        };
+        let inside = chunks[13].unpack_blocks();
+        if level == 0 {
+            for z in 0..32 {
+                for y in 0..32 {
+                    let start = neighborhood.index(ivec3(0, y, z));
+                    let inside_start = ((y << 5) + (z << 10)) as usize;
+                    neighborhood.blocks[start..start + 32]
+                        .copy_from_slice(&inside[inside_start..inside_start + 32]);
+                }
+            }
+        }
+        for z in -1..cells + 1 {
+            for y in -1..cells + 1 {
+                let border_row = y == -1 || y == cells || z == -1 || z == cells;
+                for x in -1..cells + 1 {
+                    let pos = ivec3(x, y, z);
+                    let i = neighborhood.index(pos);
+                    if level > 0 {
+                        neighborhood.blocks[i] =
+                            neighborhood.cell_block(chunks, &inside, registry, pos);
+                    } else if border_row || x == -1 || x == 32 {
+                        neighborhood.blocks[i] = neighborhood_block(chunks, pos).pack();
+                    }
+                }
+            }
+        }
+        let blocks = &neighborhood.blocks;
+        let mut last = (blocks[0], registry.opacity(Block::unpack(blocks[0])));
+        for (i, block) in blocks.iter().enumerate() {
+            if *block != last.0 {
+                last = (*block, registry.opacity(Block::unpack(*block)));
+            }
+            let bit = 1 << (i % size);
+            match last.1 {
+                Opacity::Opaque => neighborhood.opaque[i / size] |= bit,
+                Opacity::Translucent => neighborhood.translucent[i / size] |= bit,
+                _ => {}
+            }
+            if last.1 != Opacity::Invisible {
+                neighborhood.visible[i / size] |= bit;
+            }
+        }
+        neighborhood
+    }
+    // the most common visible block of the cell when at least half of it is visible,
+    // its most common invisible block otherwise
+    fn cell_block(
+        &self,
+        chunks: &[Arc<Chunk>; 27],
+        inside: &[u32],
+        registry: &BlockRegistry,
+        cell: IVec3,
+    ) -> u32 {
+        // packed blocks with their count
+        let mut counts: Vec<(u32, u32)> = vec![];
+        let mut visible = 0;
+        let start = cell * self.scale;
+        for z in start.z..start.z + self.scale {
+            for y in start.y..start.y + self.scale {
+                for x in start.x..start.x + self.scale {
+                    let in_chunk = (x | y | z) & !31 == 0;
+                    let block = if in_chunk {
+                        inside[(x + (y << 5) + (z << 10)) as usize]
+                    } else {
+                        neighborhood_block(chunks, ivec3(x, y, z)).pack()
+                    };
+                    match counts.iter_mut().find(|count| count.0 == block) {
+                        Some(count) => count.1 += 1,
+                        None => counts.push((block, 1)),
+                    }
+                }
+            }
+        }
+        let is_visible = |block: u32| registry.opacity(Block::unpack(block)) != Opacity::Invisible;
+        for count in &counts {
+            if is_visible(count.0) {
+                visible += count.1;
+            }
+        }
+        let show = visible * 2 >= (self.scale * self.scale * self.scale) as u32;
+        counts
+            .iter()
+            .filter(|count| is_visible(count.0) == show)
+            .max_by_key(|count| count.1)
+            .map_or(0, |count| count.0)
+    }
+    fn index(&self, pos: IVec3) -> usize {
+        (pos.x + 1) as usize + (self.cells + 2) as usize * self.row(pos.y, pos.z)
+    }
+    fn row(&self, y: i32, z: i32) -> usize {
+        (y + 1) as usize + (self.cells + 2) as usize * (z + 1) as usize
+    }
+    fn block(&self, pos: IVec3) -> Block {
+        Block::unpack(self.blocks[self.index(pos)])
+    }
+    fn is_opaque(&self, pos: IVec3) -> bool {
+        self.opaque[self.row(pos.y, pos.z)] >> (pos.x + 1) & 1 == 1
+    }
+    // adds a face for each bit of the mask, bit x + 1 standing for the cell at origin + x,
+    // faces toward the negative side belong to its neighbor on the positive side
+    fn add_faces(
+        &self,
+        registry: &BlockRegistry,
+        mesh: &mut ChunkMesh,
+        translucent: &mut Vec<(u32, Face)>,
+        mut mask: u64,
+        origin: IVec3,
+        dir: u8,
+    ) {
+        let offset = if dir % 2 == 0 {
+            Direction { id: dir + 1 }.get_norm()
+        } else {
+            IVec3::ZERO
+        };
+        let dir = Direction { id: dir };
+        // faces of cells cover scale blocks along both axes of their texture,
+        // and the ones toward the positive side lie on the far side of the cell
+        let far_side = dir.get_norm().max(IVec3::ZERO) * (self.scale - 1);
+        let size = (self.scale as u32 - 1) | (self.scale as u32 - 1) << 5;
+        while mask != 0 {
+            let x = mask.trailing_zeros() as i32 - 1;
+            mask &= mask - 1;
+            let pos = origin + ivec3(x, 0, 0) + offset;
+            let block = self.block(pos);
+            let mut ao = [false; 8];
+            for (ao, ao_offset) in ao.iter_mut().zip(AO_OFFSETS.iter()) {
+                *ao = self.is_opaque(pos + dir.transform(*ao_offset));
+            }
+            let block_pos = (pos * self.scale + far_side).as_uvec3();
+            let face = Face {
+                size,
+                ..create_face(registry, block_pos, &dir, block, ao)
+            };
+            if registry.opacity(block) == Opacity::Translucent {
+                // create_mesh_per_block adds the faces of each pair of blocks from the
+                // positive one of the x axis to the negative one of the y axis
+                let pair = ivec3(x, origin.y, origin.z) + IVec3::ONE;
+                let key = ((pair.y * MAX_SIZE + pair.z) * MAX_SIZE + pair.x) as u32 * 6
+                    + (dir.id ^ 1) as u32;
+                translucent.push((key, face));
+            } else {
+                mesh.faces[dir.id as usize].push(face);
+            }
+        }
+    }
+}
+
+// reads every block and its neighbors one by one, kept as the reference create_mesh has to match
+#[profiling::function]
+pub fn create_mesh_per_block(chunks: &[Arc<Chunk>; 27], registry: &BlockRegistry) -> ChunkMesh {
+    let mut mesh = ChunkMesh::new();
+    for y in 0..32 {
+        for z in 0..32 {
+            for x in 0..32 {
+                let pos = uvec3(x, y, z);
+                let block = chunks[13].get_block(pos);
+                let neighbor = if x < 31 {
+                    chunks[13].get_block(uvec3(x + 1, y, z))
+                } else {
+                    chunks[13 + 1].get_block(uvec3(0, y, z))
+                };
+                add_block_faces(registry, chunks, &mut mesh, pos, block, neighbor, 1);
+                let neighbor = if z < 31 {
+                    chunks[13].get_block(uvec3(x, y, z + 1))
+                } else {
+                    chunks[13 + 9].get_block(uvec3(x, y, 0))
+                };
+                add_block_faces(registry, chunks, &mut mesh, pos, block, neighbor, 3);
+                let neighbor = if y < 31 {
+                    chunks[13].get_block(uvec3(x, y + 1, z))
+                } else {
+                    chunks[13 + 3].get_block(uvec3(x, 0, z))
+                };
+                add_block_faces(registry, chunks, &mut mesh, pos, block, neighbor, 5);
+            }
+        }
+    }
+    mesh
+}
+// faces between a block and its neighbor on the positive side along dir
+fn add_block_faces(
+    registry: &BlockRegistry,
+    chunks: &[Arc<Chunk>; 27],
+    mesh: &mut ChunkMesh,
+    pos: UVec3,
+    block: Block,
+    neighbor: Block,
+    dir: u8,
+) {
+    if registry.face_visible(block, neighbor) {
+        add_block_face(registry, chunks, mesh, pos, Direction { id: dir }, block);
+    }
+    if registry.face_visible(neighbor, block) {
+        let neighbor_pos = (pos.as_ivec3() + Direction { id: dir }.get_norm()).as_uvec3();
+        let dir = Direction { id: dir - 1 };
+        add_block_face(registry, chunks, mesh, neighbor_pos, dir, neighbor);
+    }
+}
+fn add_block_face(
+    registry: &BlockRegistry,
+    chunks: &[Arc<Chunk>; 27],
+    mesh: &mut ChunkMesh,
+    pos: UVec3,
+    dir: Direction,
+    block: Block,
+) {
+    let mut ao = [false; 8];
+    for (ao, offset) in ao.iter_mut().zip(AO_OFFSETS.iter()) {
+        *ao = registry.is_opaque(neighborhood_block(
+            chunks,
+            pos.as_ivec3() + dir.transform(*offset),
+        ));
+    }
+    let face = create_face(registry, pos, &dir, block, ao);
+    if registry.opacity(block) == Opacity::Translucent {
+        mesh.translucent.push(face);
+    } else {
+        mesh.faces[dir.id as usize].push(face);
+    }
+}
@@ -1,150 +1,896 @@
-use crate::chunk::Chunk;
-use bytemuck::Contiguous;
-use glam::IVec3;
+use crate::chunk::{widen_palette, Chunk, ChunkDecodeError};
+use crate::world_meta::timestamp;
+use anyhow::{bail, Result};
+use glam::{ivec3, IVec3};
+use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::fs;
 use std::fs::{File, OpenOptions};
+use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"RVXR";
+// 0 is the headerless format, files of older versions are migrated when opened
+pub const REGION_VERSION: u32 = 4;
+pub const CHUNK_DIMS: [u16; 3] = [32, 32, 32];
+pub const REGION_DIMS: [u16; 3] = [16, 4, 16];
+const GENERATOR_LEN: usize = 32;
+const HEADER_SIZE: u32 = 64;
+const INDEX_SIZE: u32 = 8192;
+const DATA_START: u32 = HEADER_SIZE + INDEX_SIZE;
+const ZSTD_LEVEL: i32 = 12;
+// records start on sector boundaries counted from DATA_START
+const SECTOR_SIZE: u32 = 256;
+// records moved by each background compaction step
+const COMPACT_MOVES: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Codec {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+impl Codec {
+    pub fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Lz4),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::None => data.to_vec(),
+            Codec::Lz4 => lz4_flex::compress_prepend_size(data),
+            Codec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).unwrap(),
+        }
+    }
+    pub fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Codec::None => Some(data.to_vec()),
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data).ok(),
+            Codec::Zstd => zstd::decode_all(data).ok(),
+        }
+    }
+}
+
+pub struct RegionHeader {
+    pub version: u32,
+    pub chunk_dims: [u16; 3],
+    pub region_dims: [u16; 3],
+    pub generator: String,
+}
+impl RegionHeader {
+    pub fn new(generator: &str) -> Self {
+        RegionHeader {
+            version: REGION_VERSION,
+            chunk_dims: CHUNK_DIMS,
+            region_dims: REGION_DIMS,
+            generator: String::from(generator),
+        }
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        for i in self.chunk_dims.iter().chain(self.region_dims.iter()) {
+            buffer.extend_from_slice(&i.to_le_bytes());
+        }
+        let mut generator = [0u8; GENERATOR_LEN];
+        let len = self.generator.len().min(GENERATOR_LEN);
+        generator[..len].copy_from_slice(&self.generator.as_bytes()[..len]);
+        buffer.extend_from_slice(&generator);
+        buffer.resize(HEADER_SIZE as usize, 0);
+        buffer
+    }
+    // files without the magic number are from before the header existed
+    fn from_bytes(buffer: &[u8]) -> Self {
+        if buffer.len() < HEADER_SIZE as usize || buffer[..4] != MAGIC {
+            return RegionHeader {
+                version: 0,
+                chunk_dims: CHUNK_DIMS,
+                region_dims: REGION_DIMS,
+                generator: String::new(),
+            };
+        }
+        let dim = |i: usize| u16::from_le_bytes(buffer[8 + i * 2..10 + i * 2].try_into().unwrap());
+        let generator = &buffer[20..20 + GENERATOR_LEN];
+        let generator_len = generator
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(GENERATOR_LEN);
+        RegionHeader {
+            version: u32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+            chunk_dims: [dim(0), dim(1), dim(2)],
+            region_dims: [dim(3), dim(4), dim(5)],
+            generator: String::from_utf8_lossy(&generator[..generator_len]).into_owned(),
+        }
+    }
+}
+
 fn pos_to_id(pos: IVec3) -> usize {
     return ((pos.x as usize & 15) + 16 * (pos.y as usize & 3) + 16 * 4 * (pos.z as usize & 15))
         * 2;
 }
+// position in the region of the chunk stored at an index location
+fn local_pos(id: usize) -> IVec3 {
+    ivec3(
+        ((id >> 1) & 15) as i32,
+        ((id >> 5) & 3) as i32,
+        (id >> 7) as i32,
+    )
+}
+pub fn region_filename(save_file: &str, pos: IVec3) -> String {
+    String::from(save_file)
+        + "/region/"
+        + &*pos.x.to_string()
+        + ","
+        + &*pos.y.to_string()
+        + ","
+        + &*pos.z.to_string()
+}
+fn sectors(len: u32) -> u32 {
+    len.div_ceil(SECTOR_SIZE)
+}
+fn sector_offset(sector: u32) -> u32 {
+    DATA_START + sector * SECTOR_SIZE
+}
+fn offset_sector(offset: u32) -> u32 {
+    (offset - DATA_START) / SECTOR_SIZE
+}
+fn index_to_bytes(index: &[u32; 2048]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(INDEX_SIZE as usize);
+    for i in index {
+        buffer.extend_from_slice(&i.to_le_bytes());
+    }
+    buffer
+}
+fn index_from_bytes(buffer: &[u8]) -> [u32; 2048] {
+    let mut index = [0; 2048];
+    for i in 0..2048 {
+        index[i] = u32::from_le_bytes(buffer[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    index
+}
+// the journal holds a complete index followed by its crc32
+fn read_journal(journal_filename: &str) -> Option<[u32; 2048]> {
+    let journal = fs::read(journal_filename).ok()?;
+    if journal.len() != INDEX_SIZE as usize + 4
+        || crc32fast::hash(&journal[..INDEX_SIZE as usize])
+            != u32::from_le_bytes(journal[INDEX_SIZE as usize..].try_into().unwrap())
+    {
+        return None;
+    }
+    Some(index_from_bytes(&journal))
+}
+fn read_record(file: &mut File, offset: u32, len: u32) -> Option<Vec<u8>> {
+    let mut buffer = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(offset as u64)).ok()?;
+    file.read_exact(&mut buffer).ok()?;
+    Some(buffer)
+}
+// records are a crc32 of the rest of the record, the codec id, then the encoded chunk data
+fn encode_record(data: &[u8], codec: Codec) -> Vec<u8> {
+    let mut record = vec![0; 4];
+    record.push(codec as u8);
+    record.extend_from_slice(&codec.encode(data));
+    let checksum = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&checksum.to_le_bytes());
+    record
+}
+// verifies the checksum of a record and decodes its data, returns None if it is corrupted
+fn decode_record(record: &[u8]) -> Option<Vec<u8>> {
+    if record.len() < 5 {
+        return None;
+    }
+    let checksum = u32::from_le_bytes(record[..4].try_into().unwrap());
+    if crc32fast::hash(&record[4..]) != checksum {
+        return None;
+    }
+    Codec::from_id(record[4])?.decode(&record[5..])
+}
+// writes a complete region file next to the original and moves it in place atomically
+fn write_shadow(
+    filename: &str,
+    header: &RegionHeader,
+    records: &[(usize, Vec<u8>)],
+) -> io::Result<(File, [u32; 2048])> {
+    let tmp_filename = String::from(filename) + ".tmp";
+    let mut tmp_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_filename)?;
+    let mut index = [0; 2048];
+    let mut pos = DATA_START;
+    for (id, record) in records {
+        tmp_file.seek(SeekFrom::Start(pos as u64))?;
+        tmp_file.write_all(record)?;
+        index[*id] = pos;
+        index[*id + 1] = record.len() as u32;
+        pos += sectors(record.len() as u32) * SECTOR_SIZE;
+    }
+    tmp_file.seek(SeekFrom::Start(0))?;
+    tmp_file.write_all(&header.to_bytes())?;
+    tmp_file.write_all(&index_to_bytes(&index))?;
+    tmp_file.sync_all()?;
+    fs::rename(&tmp_filename, filename)?;
+    sync_dir(filename)?;
+    Ok((tmp_file, index))
+}
+// makes the files created, renamed or removed next to the file durable, directories
+// can't be opened to be synced on windows
+fn sync_dir(filename: &str) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        if let Some(dir) = Path::new(filename).parent() {
+            File::open(dir)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+// writes the index of a journal left by a crash to the index of the file at index_offset
+// and returns it, an incomplete journal was never committed and is dropped
+fn apply_journal(
+    file: &mut File,
+    filename: &str,
+    index_offset: u32,
+) -> io::Result<Option<[u32; 2048]>> {
+    let journal_filename = String::from(filename) + ".journal";
+    if !Path::new(&journal_filename).exists() {
+        return Ok(None);
+    }
+    let index = read_journal(&journal_filename);
+    match index {
+        Some(index) => {
+            file.seek(SeekFrom::Start(index_offset as u64))?;
+            file.write_all(&index_to_bytes(&index))?;
+            file.sync_data()?;
+        }
+        None => log::warn!("{}: discarding incomplete journal", filename),
+    }
+    fs::remove_file(&journal_filename)?;
+    sync_dir(filename)?;
+    Ok(index)
+}
+
+// version 0 files start directly with the index, their records may lack the checksum
+// and codec id depending on the flags set on their size
+mod v0 {
+    use super::*;
+    const CHECKSUM_FLAG: u32 = 1 << 31;
+    const CODEC_FLAG: u32 = 1 << 30;
+
+    fn decode_record(size: u32, record: &[u8]) -> Option<Vec<u8>> {
+        if size & CHECKSUM_FLAG == 0 {
+            return Some(record.to_vec());
+        }
+        if record.len() < 4 {
+            return None;
+        }
+        let checksum = u32::from_le_bytes(record[..4].try_into().unwrap());
+        if crc32fast::hash(&record[4..]) != checksum {
+            return None;
+        }
+        if size & CODEC_FLAG == 0 {
+            return Some(record[4..].to_vec());
+        }
+        Codec::from_id(*record.get(4)?)?.decode(&record[5..])
+    }
+    pub fn read_chunks(file: &mut File, filename: &str) -> io::Result<Vec<(usize, Vec<u8>)>> {
+        let mut buffer = vec![0u8; INDEX_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut buffer)?;
+        let index = index_from_bytes(&buffer);
+        let mut chunks = vec![];
+        for i in (0..2048).step_by(2) {
+            let len = index[i + 1] & !(CHECKSUM_FLAG | CODEC_FLAG);
+            if len == 0 {
+                continue;
+            }
+            match read_record(file, index[i], len)
+                .and_then(|record| decode_record(index[i + 1], &record))
+            {
+                Some(data) => chunks.push((i, data)),
+                None => log::warn!("{}: dropping corrupted chunk {}", filename, i / 2),
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+// version 1, 2 and 3 files store records in the current format, without sector alignment
+// for version 1, but their chunks lack block states before version 3 and heightmaps
+// before version 4
+mod v1 {
+    use super::*;
+
+    pub fn read_chunks(file: &mut File, filename: &str) -> io::Result<Vec<(usize, Vec<u8>)>> {
+        let mut buffer = vec![0u8; INDEX_SIZE as usize];
+        file.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        file.read_exact(&mut buffer)?;
+        let index = index_from_bytes(&buffer);
+        let mut chunks = vec![];
+        for i in (0..2048).step_by(2) {
+            if index[i + 1] == 0 {
+                continue;
+            }
+            match read_record(file, index[i], index[i + 1])
+                .and_then(|record| decode_record(&record))
+            {
+                Some(data) => chunks.push((i, data)),
+                None => log::warn!("{}: dropping corrupted chunk {}", filename, i / 2),
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+// free runs of sectors as (first sector, sector count), sorted and never adjacent
+struct FreeList {
+    runs: Vec<(u32, u32)>,
+}
+impl FreeList {
+    // first fit among the runs starting before the given sector
+    fn allocate(&mut self, count: u32, before: u32) -> Option<u32> {
+        let i = self
+            .runs
+            .iter()
+            .position(|run| run.0 < before && run.1 >= count)?;
+        let start = self.runs[i].0;
+        if self.runs[i].1 == count {
+            self.runs.remove(i);
+        } else {
+            self.runs[i].0 += count;
+            self.runs[i].1 -= count;
+        }
+        Some(start)
+    }
+    fn release(&mut self, start: u32, count: u32) {
+        let i = self.runs.partition_point(|run| run.0 < start);
+        self.runs.insert(i, (start, count));
+        if i + 1 < self.runs.len() && start + count == self.runs[i + 1].0 {
+            self.runs[i].1 += self.runs[i + 1].1;
+            self.runs.remove(i + 1);
+        }
+        if i > 0 && self.runs[i - 1].0 + self.runs[i - 1].1 == start {
+            self.runs[i - 1].1 += self.runs[i].1;
+            self.runs.remove(i);
+        }
+    }
+}
+
+// The index after the header is only ever updated through the journal:
+// the new index is first written and synced to "<region>.journal", then copied in place.
+// Chunk data is never written over space referenced by the last committed index,
+// so a crash at any point leaves either the old or the new index pointing at valid data.
+// Sectors of replaced committed records are kept in pending_free until the next flush.
 pub struct Region {
     index: [u32; 2048],
+    committed_index: [u32; 2048],
+    header: RegionHeader,
     file: File,
+    filename: String,
+    pos: IVec3,
+    quarantine_dir: String,
+    codec: Codec,
+    dirty: bool,
+    free: FreeList,
+    pending_free: Vec<(u32, u32)>,
+    // sectors between DATA_START and the end of the file
+    sector_count: u32,
     pub chunk_count: u32,
 }
 impl Region {
-    pub fn new(save_file: String, pos: IVec3) -> Self {
+    pub fn new(save_file: String, pos: IVec3, codec: Codec, generator: &str) -> Result<Self> {
         if !Path::new(&(save_file.clone() + "/region")).exists() {
-            fs::create_dir_all(save_file.clone() + "/region").unwrap();
-        }
-        let filename = save_file.clone()
-            + "/region/"
-            + &*pos.x.to_string()
-            + ","
-            + &*pos.y.to_string()
-            + ","
-            + &*pos.z.to_string();
-        let mut index = [0; 2048];
-        let file: File = match OpenOptions::new().read(true).write(true).open(&filename) {
-            Ok(mut open_file) => {
-                open_file.seek(SeekFrom::Start(0)).unwrap();
-                let mut buffer = vec![0u8; 8192];
-                open_file.read_exact(&mut buffer).unwrap();
-                for i in 0..2048 {
-                    index[i] = u32::from_le_bytes(buffer[i * 4..i * 4 + 4].try_into().unwrap());
-                }
-                open_file
-            }
-            Err(_) => {
-                let mut openfile = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .open(&filename)
-                    .unwrap();
-                for i in (0..2048).step_by(2) {
-                    index[i] = 8192;
-                    index[i + 1] = 0;
-                }
-                openfile.write_all(&[0; 8192]).unwrap();
-                openfile
+            fs::create_dir_all(save_file.clone() + "/region")?;
+        }
+        let filename = region_filename(&save_file, pos);
+        // leftover of an interrupted compaction or migration, the region file itself is still intact
+        let _ = fs::remove_file(filename.clone() + ".tmp");
+        let mut file = match OpenOptions::new().read(true).write(true).open(&filename) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                write_shadow(&filename, &RegionHeader::new(generator), &[])?;
+                OpenOptions::new().read(true).write(true).open(&filename)?
             }
+            Err(e) => return Err(e.into()),
+        };
+        let mut buffer = vec![];
+        (&mut file)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut buffer)?;
+        let mut header = RegionHeader::from_bytes(&buffer);
+        // a truncated file is left as it is for the chunks it still holds, the region stays
+        // unavailable until it is moved away
+        let file_len = file.metadata()?.len();
+        let min_len = if header.version == 0 {
+            INDEX_SIZE
+        } else {
+            DATA_START
         };
-        Region {
+        if file_len < min_len as u64 {
+            bail!(
+                "{} is truncated to {} bytes, its index needs {}, move it away to generate the region again",
+                filename,
+                file_len,
+                min_len
+            );
+        }
+        if header.version > REGION_VERSION {
+            bail!(
+                "{} uses region format version {}, this build only supports up to version {}",
+                filename,
+                header.version,
+                REGION_VERSION
+            );
+        }
+        if header.chunk_dims != CHUNK_DIMS || header.region_dims != REGION_DIMS {
+            bail!(
+                "{} stores {:?} regions of {:?} chunks, expected {:?} regions of {:?} chunks",
+                filename,
+                header.region_dims,
+                header.chunk_dims,
+                REGION_DIMS,
+                CHUNK_DIMS
+            );
+        }
+        if header.version < REGION_VERSION {
+            log::info!(
+                "{}: migrating from region format version {} to {}",
+                filename,
+                header.version,
+                REGION_VERSION
+            );
+            // the journal of the old file goes first, left behind it would be replayed
+            // onto the migrated file whose records are elsewhere
+            let index_offset = if header.version == 0 { 0 } else { HEADER_SIZE };
+            apply_journal(&mut file, &filename, index_offset)?;
+            let chunks = if header.version == 0 {
+                v0::read_chunks(&mut file, &filename)?
+            } else {
+                v1::read_chunks(&mut file, &filename)?
+            };
+            let records: Vec<(usize, Vec<u8>)> = chunks
+                .iter()
+                .map(|(id, data)| {
+                    // block states came with version 3, the heightmaps of older
+                    // chunks are computed when they are loaded
+                    let data = if header.version < 3 {
+                        widen_palette(data)
+                    } else {
+                        data.clone()
+                    };
+                    (*id, encode_record(&data, codec))
+                })
+                .collect();
+            header = RegionHeader::new(generator);
+            file = write_shadow(&filename, &header, &records)?.0;
+        } else if header.generator != generator {
+            log::warn!(
+                "{} was generated by \"{}\", now generating with \"{}\"",
+                filename,
+                header.generator,
+                generator
+            );
+        }
+        let mut buffer = vec![0u8; INDEX_SIZE as usize];
+        file.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        file.read_exact(&mut buffer)?;
+        let index = index_from_bytes(&buffer);
+        let mut region = Region {
             index,
+            committed_index: index,
+            header,
             file,
+            filename,
+            pos,
+            quarantine_dir: save_file + "/quarantine",
+            codec,
+            dirty: false,
+            free: FreeList { runs: vec![] },
+            pending_free: vec![],
+            sector_count: 0,
             chunk_count: 0,
-        }
+        };
+        region.recover()?;
+        region.rebuild_free_list()?;
+        Ok(region)
     }
     #[profiling::function]
-    fn shrink_to_fit(&mut self) {
-        let mut pos = 8192;
-        let mut old_pos = 0;
-        loop {
-            let mut min = u32::MAX_VALUE;
-            let mut index = 0;
-            for j in (0..2048).step_by(2) {
-                if self.index[j] > old_pos && self.index[j] < min && self.index[j + 1] != 0 {
-                    min = self.index[j];
-                    index = j;
+    fn recover(&mut self) -> io::Result<()> {
+        // a journal that is complete was committed, the index in the file may be partially written
+        if let Some(index) = apply_journal(&mut self.file, &self.filename, HEADER_SIZE)? {
+            self.index = index;
+        }
+        let file_len = self.file.metadata()?.len();
+        let mut corrupted = false;
+        for i in (0..2048).step_by(2) {
+            let len = self.index[i + 1];
+            if len == 0 {
+                continue;
+            }
+            let in_file = self.index[i] >= DATA_START
+                && (self.index[i] - DATA_START) % SECTOR_SIZE == 0
+                && self.index[i] as u64 + len as u64 <= file_len;
+            let record = if in_file {
+                read_record(&mut self.file, self.index[i], len)
+            } else {
+                None
+            };
+            if record
+                .as_ref()
+                .and_then(|record| decode_record(record))
+                .is_none()
+            {
+                log::warn!(
+                    "{}: dropping corrupted chunk {} (offset {}, size {})",
+                    self.filename,
+                    i / 2,
+                    self.index[i],
+                    len
+                );
+                if let Some(record) = record {
+                    self.quarantine(self.chunk_pos(i), &record);
                 }
+                self.index[i] = 0;
+                self.index[i + 1] = 0;
+                corrupted = true;
+            }
+        }
+        self.committed_index = self.index;
+        if corrupted {
+            self.dirty = true;
+            self.flush()?;
+        }
+        Ok(())
+    }
+    // rewrites the live records contiguously into a shadow file that replaces the region atomically
+    #[profiling::function]
+    pub fn shrink_to_fit(&mut self) -> io::Result<()> {
+        let mut records = vec![];
+        for i in (0..2048).step_by(2) {
+            if self.index[i + 1] != 0 {
+                let record = read_record(&mut self.file, self.index[i], self.index[i + 1])
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                records.push((i, record));
             }
-            if min == u32::MAX_VALUE {
-                break;
+        }
+        let (file, index) = write_shadow(&self.filename, &self.header, &records)?;
+        self.file = file;
+        self.index = index;
+        self.committed_index = index;
+        self.dirty = false;
+        self.pending_free.clear();
+        self.rebuild_free_list()
+    }
+    // every sector not used by a record of the index is free
+    fn rebuild_free_list(&mut self) -> io::Result<()> {
+        let mut used = vec![];
+        for i in (0..2048).step_by(2) {
+            if self.index[i + 1] != 0 {
+                used.push((offset_sector(self.index[i]), sectors(self.index[i + 1])));
             }
-            old_pos = min;
-            if min != pos {
-                self.index[index] = pos;
-                let mut buffer = vec![0; self.index[index + 1] as usize];
-                self.file.seek(SeekFrom::Start(min as u64)).unwrap();
-                self.file.read_exact(&mut buffer).unwrap();
-                self.file.seek(SeekFrom::Start(pos as u64)).unwrap();
-                self.file.write_all(&mut buffer).unwrap();
+        }
+        used.sort();
+        self.free.runs.clear();
+        let mut end = 0;
+        for (start, count) in used {
+            if start > end {
+                self.free.runs.push((end, start - end));
             }
-            pos += self.index[index + 1];
+            end = end.max(start + count);
+        }
+        self.sector_count = end;
+        self.file.set_len(sector_offset(end) as u64)
+    }
+    // gives back the sectors of a record that is being replaced
+    fn release(&mut self, id: usize) {
+        if self.index[id + 1] == 0 {
+            return;
+        }
+        let run = (offset_sector(self.index[id]), sectors(self.index[id + 1]));
+        if self.committed_index[id] == self.index[id] && self.committed_index[id + 1] != 0 {
+            self.pending_free.push(run);
+        } else {
+            self.free.release(run.0, run.1);
         }
-        self.file.set_len(pos as u64).unwrap();
     }
+    fn write_record(&mut self, id: usize, record: &[u8], sector: u32) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(sector_offset(sector) as u64))?;
+        self.file.write_all(record)?;
+        self.index[id] = sector_offset(sector);
+        self.index[id + 1] = record.len() as u32;
+        self.sector_count = self.sector_count.max(sector + sectors(record.len() as u32));
+        self.dirty = true;
+        Ok(())
+    }
+    // moves the records at the end of the file into free space before them,
+    // the emptied tail is truncated by the next flush, returns whether a record was moved
     #[profiling::function]
-    pub fn save_index(&mut self) {
-        let mut buffer = vec![0; 2048];
-        for i in 0..2048 {
-            buffer[i] = self.index[i].to_le();
+    pub fn compact_step(&mut self) -> io::Result<bool> {
+        for moves in 0..COMPACT_MOVES {
+            let last = (0..2048)
+                .step_by(2)
+                .filter(|i| self.index[i + 1] != 0)
+                .max_by_key(|i| self.index[*i]);
+            let id = match last {
+                Some(id) => id,
+                None => return Ok(moves > 0),
+            };
+            let start = offset_sector(self.index[id]);
+            let sector = match self.free.allocate(sectors(self.index[id + 1]), start) {
+                Some(sector) => sector,
+                None => return Ok(moves > 0),
+            };
+            let record = match read_record(&mut self.file, self.index[id], self.index[id + 1]) {
+                Some(record) => record,
+                None => {
+                    self.free.release(sector, sectors(self.index[id + 1]));
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+            };
+            self.release(id);
+            self.write_record(id, &record, sector)?;
         }
-        self.file.seek(SeekFrom::Start(0)).unwrap();
-        self.file.write_all(bytemuck::cast_slice(&buffer)).unwrap();
+        Ok(true)
     }
     #[profiling::function]
-    fn available_space(&self, id: usize) -> u32 {
-        let mut space = i32::MAX;
-        for i in (0..2048).step_by(2) {
-            let diff = self.index[i] as i32 - self.index[id] as i32;
-            if diff >= 0 && diff < space {
-                space = diff;
+    pub fn save_index(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        self.file.write_all(&index_to_bytes(&self.index))
+    }
+    // makes every chunk saved since the last flush durable
+    #[profiling::function]
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.file.sync_data()?;
+        let journal_filename = self.filename.clone() + ".journal";
+        let mut journal = index_to_bytes(&self.index);
+        journal.extend_from_slice(&crc32fast::hash(&journal).to_le_bytes());
+        let mut journal_file = File::create(&journal_filename)?;
+        journal_file.write_all(&journal)?;
+        journal_file.sync_all()?;
+        sync_dir(&self.filename)?;
+        self.save_index()?;
+        self.file.sync_data()?;
+        fs::remove_file(&journal_filename)?;
+        self.committed_index = self.index;
+        self.dirty = false;
+        for (start, count) in self.pending_free.drain(..) {
+            self.free.release(start, count);
+        }
+        if let Some(&(start, count)) = self.free.runs.last() {
+            if start + count >= self.sector_count {
+                self.free.runs.pop();
+                self.sector_count = start;
+                self.file.set_len(sector_offset(start) as u64)?;
             }
         }
-        return space as u32;
+        Ok(())
     }
     #[profiling::function]
-    pub fn save_chunk(&mut self, mut chunk: Box<Chunk>, pos: IVec3) {
-        self.chunk_count -= 1;
+    pub fn save_chunk(&mut self, mut chunk: Box<Chunk>, pos: IVec3) -> io::Result<()> {
+        // chunks that were never loaded from this region may still be saved to it
+        self.chunk_count = self.chunk_count.saturating_sub(1);
         let location = pos_to_id(pos);
-        let data = chunk.serialize();
-        self.index[location + 1] = data.len() as u32;
-        if self.available_space(location) < data.len() as u32 {
-            self.index[location] = self.file.seek(SeekFrom::End(0)).unwrap() as u32;
-        } else {
-            self.file
-                .seek(SeekFrom::Start(self.index[location] as u64))
-                .unwrap();
-        }
-        self.file.write_all(&data).unwrap();
+        chunk.compact();
+        let record = encode_record(&chunk.serialize(), self.codec);
+        // space still referenced by the committed index can't be overwritten before the next flush
+        self.release(location);
+        let sector = self
+            .free
+            .allocate(sectors(record.len() as u32), u32::MAX)
+            .unwrap_or(self.sector_count);
+        self.write_record(location, &record, sector)
     }
+    // chunks that can't be decoded are moved to the quarantine directory and removed from the region
     #[profiling::function]
-    pub fn load_chunk(&mut self, pos: IVec3) -> Option<Box<Chunk>> {
+    pub fn load_chunk(&mut self, pos: IVec3) -> Result<Option<Box<Chunk>>, ChunkDecodeError> {
         self.chunk_count += 1;
         let location = pos_to_id(pos);
-        let data_size = self.index[location + 1] as usize;
+        let data_size = self.index[location + 1];
 
         if data_size == 0 {
-            return None;
+            return Ok(None);
         }
+        let record = read_record(&mut self.file, self.index[location], data_size);
         let mut chunk = Box::new(Chunk::new());
-        self.file
-            .seek(SeekFrom::Start(self.index[location] as u64))
-            .unwrap();
-        let mut buffer = vec![0u8; data_size];
-        self.file.read_exact(&mut buffer).unwrap();
-        chunk.deserialize(&buffer);
-        Some(chunk)
+        let result = match record.as_ref().and_then(|record| decode_record(record)) {
+            Some(data) => chunk.deserialize(&data),
+            None => Err(ChunkDecodeError::Record),
+        };
+        match result {
+            Ok(()) => Ok(Some(chunk)),
+            Err(e) => {
+                self.quarantine(pos, record.as_deref().unwrap_or(&[]));
+                self.release(location);
+                self.index[location] = 0;
+                self.index[location + 1] = 0;
+                self.dirty = true;
+                Err(e)
+            }
+        }
+    }
+    fn chunk_pos(&self, id: usize) -> IVec3 {
+        self.pos * ivec3(16, 4, 16) + local_pos(id)
+    }
+    fn quarantine(&self, pos: IVec3, record: &[u8]) {
+        let filename = format!(
+            "{}/{},{},{}-{}",
+            self.quarantine_dir,
+            pos.x,
+            pos.y,
+            pos.z,
+            timestamp()
+        );
+        let result =
+            fs::create_dir_all(&self.quarantine_dir).and_then(|_| fs::write(&filename, record));
+        match result {
+            Ok(()) => log::warn!("{}: chunk {} moved to {}", self.filename, pos, filename),
+            Err(e) => log::error!(
+                "{}: could not quarantine chunk {}: {}",
+                self.filename,
+                pos,
+                e
+            ),
+        }
     }
 }
 impl Drop for Region {
     fn drop(&mut self) {
-        self.shrink_to_fit();
-        self.save_index();
+        if let Err(e) = self.flush() {
+            log::error!("{}: could not be saved: {}", self.filename, e);
+        }
+    }
+}
+
+pub struct RegionEntry {
+    pub id: usize,
+    pub offset: u32,
+    pub size: u32,
+}
+impl RegionEntry {
+    // position of the chunk relative to the first chunk of the region
+    pub fn local_pos(&self) -> IVec3 {
+        local_pos(self.id * 2)
+    }
+}
+// read-only access to a region file, to inspect saves without recovering or migrating them
+pub struct RegionReader {
+    pub header: RegionHeader,
+    pub journal: bool,
+    index: [u32; 2048],
+    file: File,
+}
+impl RegionReader {
+    pub fn open(filename: &str) -> Result<Self> {
+        let mut file = File::open(filename)?;
+        let mut buffer = vec![];
+        (&mut file)
+            .take(DATA_START as u64)
+            .read_to_end(&mut buffer)?;
+        let header = RegionHeader::from_bytes(&buffer);
+        if header.version != REGION_VERSION {
+            bail!(
+                "{} uses region format version {}, it has to be migrated to version {} first",
+                filename,
+                header.version,
+                REGION_VERSION
+            );
+        }
+        if buffer.len() < DATA_START as usize {
+            bail!("{} is truncated inside its index", filename);
+        }
+        Ok(RegionReader {
+            header,
+            journal: Path::new(&(String::from(filename) + ".journal")).exists(),
+            index: index_from_bytes(&buffer[HEADER_SIZE as usize..]),
+            file,
+        })
+    }
+    pub fn file_len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+    pub fn entries(&self) -> Vec<RegionEntry> {
+        let mut entries = vec![];
+        for i in (0..2048).step_by(2) {
+            if self.index[i + 1] != 0 {
+                entries.push(RegionEntry {
+                    id: i / 2,
+                    offset: self.index[i],
+                    size: self.index[i + 1],
+                });
+            }
+        }
+        entries
+    }
+    pub fn entry(&self, pos: IVec3) -> Option<RegionEntry> {
+        let location = pos_to_id(pos);
+        if self.index[location + 1] == 0 {
+            return None;
+        }
+        Some(RegionEntry {
+            id: location / 2,
+            offset: self.index[location],
+            size: self.index[location + 1],
+        })
+    }
+    // returns the serialized chunk, None if the record is corrupted
+    pub fn read_chunk(&mut self, entry: &RegionEntry) -> Option<Vec<u8>> {
+        read_record(&mut self.file, entry.offset, entry.size)
+            .and_then(|record| decode_record(&record))
+    }
+    pub fn verify(&mut self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.journal {
+            problems.push(String::from(
+                "has a pending journal, it is applied when opened",
+            ));
+        }
+        let file_len = match self.file_len() {
+            Ok(file_len) => file_len,
+            Err(e) => {
+                problems.push(format!("can't be read: {}", e));
+                return problems;
+            }
+        };
+        let mut entries = self.entries();
+        entries.sort_by_key(|entry| entry.offset);
+        let name = |entry: &RegionEntry| {
+            let pos = entry.local_pos();
+            format!("{},{},{}", pos.x, pos.y, pos.z)
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.offset < DATA_START {
+                problems.push(format!(
+                    "chunk {} starts inside the header at {}",
+                    name(entry),
+                    entry.offset
+                ));
+            } else if (entry.offset - DATA_START) % SECTOR_SIZE != 0 {
+                problems.push(format!(
+                    "chunk {} is not aligned on a sector at {}",
+                    name(entry),
+                    entry.offset
+                ));
+            } else if entry.offset as u64 + entry.size as u64 > file_len {
+                problems.push(format!(
+                    "chunk {} ends at {}, past the end of the file at {}",
+                    name(entry),
+                    entry.offset as u64 + entry.size as u64,
+                    file_len
+                ));
+            } else {
+                match self.read_chunk(entry) {
+                    Some(data) => {
+                        if let Err(e) = Chunk::new().deserialize(&data) {
+                            problems.push(format!("chunk {} is invalid: {}", name(entry), e));
+                        }
+                    }
+                    None => problems.push(format!(
+                        "chunk {} fails its checksum or can't be decoded",
+                        name(entry)
+                    )),
+                }
+            }
+            if let Some(next) = entries.get(i + 1) {
+                // offsets and sizes come from a possibly corrupted index
+                if entry.offset as u64 + entry.size as u64 > next.offset as u64 {
+                    problems.push(format!(
+                        "chunk {} overlaps chunk {}",
+                        name(entry),
+                        name(next)
+                    ));
+                }
+            }
+        }
+        problems
     }
 }
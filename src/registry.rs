@@ -0,0 +1,264 @@
+use crate::block::{Block, Property};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// names of the block types of worlds saved before the registry, in the order of their ids
+pub const LEGACY_BLOCKS: [&str; 7] = ["air", "grass", "dirt", "stone", "brick", "log", "leaves"];
+// the texture id of a face is stored on 10 bits
+const MAX_TEXTURES: usize = 1024;
+// in the order of the direction ids
+const FACE_NAMES: [&str; 6] = ["west", "east", "north", "south", "bottom", "top"];
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opacity {
+    Opaque,
+    // textures are either opaque or fully transparent, like leaves
+    Cutout,
+    // textures are blended with what is behind them, like glass
+    Translucent,
+    // no face is drawn, like air
+    Invisible,
+}
+
+// a face uses its own texture if set, then side for the vertical faces, then all
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct FaceTextures {
+    pub all: Option<String>,
+    pub side: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub west: Option<String>,
+    pub east: Option<String>,
+    pub north: Option<String>,
+    pub south: Option<String>,
+}
+impl FaceTextures {
+    // faces are in the order of the direction ids
+    fn face(&self, dir: usize) -> Option<&String> {
+        let own = match dir {
+            0 => &self.west,
+            1 => &self.east,
+            2 => &self.north,
+            3 => &self.south,
+            4 => &self.bottom,
+            _ => &self.top,
+        };
+        let side = if dir < 4 { &self.side } else { &None };
+        own.as_ref().or(side.as_ref()).or(self.all.as_ref())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+fn default_opacity() -> Opacity {
+    Opacity::Opaque
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BlockDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub textures: FaceTextures,
+    #[serde(default = "default_opacity")]
+    pub opacity: Opacity,
+    #[serde(default = "default_true")]
+    pub collision: bool,
+    // light level emitted, from 0 to 15
+    #[serde(default)]
+    pub light: u8,
+    // properties stored in the state of the blocks
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+impl BlockDefinition {
+    fn air() -> Self {
+        BlockDefinition {
+            name: String::from("air"),
+            textures: FaceTextures::default(),
+            opacity: Opacity::Invisible,
+            collision: false,
+            light: 0,
+            properties: vec![],
+        }
+    }
+    // stands for block types missing from the definitions, their blocks are kept but not shown
+    fn placeholder(name: &str) -> Self {
+        BlockDefinition {
+            name: String::from(name),
+            collision: true,
+            ..BlockDefinition::air()
+        }
+    }
+}
+
+pub struct BlockRegistry {
+    // indexed by block type
+    definitions: Vec<BlockDefinition>,
+    ids: HashMap<String, u16>,
+    // ids of the definitions in the order of the file
+    defined: Vec<u16>,
+    // layers of the texture array
+    textures: Vec<String>,
+    face_textures: Vec<[u32; 6]>,
+    unknown: BlockDefinition,
+}
+impl BlockRegistry {
+    pub fn load(filename: &Path, mapping: &mut Vec<String>) -> Result<Self> {
+        let text = fs::read_to_string(filename)
+            .with_context(|| format!("{} could not be read", filename.display()))?;
+        // texture names can be written without Some(...)
+        let definitions: Vec<BlockDefinition> = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str(&text)
+            .with_context(|| format!("{} is not valid", filename.display()))?;
+        BlockRegistry::new(definitions, mapping)
+            .with_context(|| format!("{} is not valid", filename.display()))
+    }
+    // mapping holds the names of the block types of a world by id, ids already used by the
+    // world are kept so the definitions can be reordered, new definitions are appended to it
+    pub fn new(definitions: Vec<BlockDefinition>, mapping: &mut Vec<String>) -> Result<Self> {
+        let mut by_name = HashMap::new();
+        for definition in &definitions {
+            if definition.name == "air" {
+                bail!("air is built in and can't be defined");
+            }
+            if by_name
+                .insert(definition.name.clone(), definition)
+                .is_some()
+            {
+                bail!("block {} is defined twice", definition.name);
+            }
+        }
+        if mapping.is_empty() {
+            mapping.push(String::from("air"));
+        } else if mapping[0] != "air" {
+            bail!("block type 0 is {} instead of air", mapping[0]);
+        }
+        for definition in &definitions {
+            if !mapping.contains(&definition.name) {
+                mapping.push(definition.name.clone());
+            }
+        }
+        if mapping.len() > u16::MAX as usize + 1 {
+            bail!(
+                "{} block types, at most {} are supported",
+                mapping.len(),
+                u16::MAX as usize + 1
+            );
+        }
+        let mut registry = BlockRegistry {
+            definitions: vec![],
+            ids: HashMap::new(),
+            defined: vec![],
+            textures: vec![],
+            face_textures: vec![],
+            unknown: BlockDefinition::placeholder("unknown"),
+        };
+        for (id, name) in mapping.iter().enumerate() {
+            let definition = if id == 0 {
+                BlockDefinition::air()
+            } else {
+                match by_name.get(name) {
+                    Some(definition) => (*definition).clone(),
+                    None => {
+                        log::warn!(
+                            "block {} is not defined anymore, its blocks are hidden",
+                            name
+                        );
+                        BlockDefinition::placeholder(name)
+                    }
+                }
+            };
+            let mut faces = [0; 6];
+            for (dir, face) in faces.iter_mut().enumerate() {
+                *face = match definition.textures.face(dir) {
+                    Some(texture) => registry.texture_id(texture)?,
+                    None if definition.opacity == Opacity::Invisible => 0,
+                    None => bail!(
+                        "block {} has no texture for its {} face",
+                        name,
+                        FACE_NAMES[dir]
+                    ),
+                };
+            }
+            registry.ids.insert(name.clone(), id as u16);
+            registry.face_textures.push(faces);
+            registry.definitions.push(definition);
+        }
+        registry.defined = definitions.iter().map(|d| registry.ids[&d.name]).collect();
+        Ok(registry)
+    }
+    fn texture_id(&mut self, texture: &str) -> Result<u32> {
+        let id = match self.textures.iter().position(|t| t == texture) {
+            Some(id) => id,
+            None => {
+                self.textures.push(String::from(texture));
+                self.textures.len() - 1
+            }
+        };
+        if id >= MAX_TEXTURES {
+            bail!("more than {} textures are used", MAX_TEXTURES);
+        }
+        Ok(id as u32)
+    }
+    pub fn get(&self, block: Block) -> &BlockDefinition {
+        self.definitions
+            .get(block.block_type as usize)
+            .unwrap_or(&self.unknown)
+    }
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.ids.get(name).copied()
+    }
+    // air when the block is not defined
+    pub fn block(&self, name: &str) -> Block {
+        Block::new(self.id(name).unwrap_or(0))
+    }
+    // for the callers that can't do with air, the blocks of removed definitions are
+    // refused too
+    pub fn defined_block(&self, name: &str) -> Result<Block> {
+        match self.id(name) {
+            Some(id) if self.defined.contains(&id) => Ok(Block::new(id)),
+            _ => bail!("block {} is not defined", name),
+        }
+    }
+    pub fn defined(&self) -> &[u16] {
+        &self.defined
+    }
+    pub fn is_opaque(&self, block: Block) -> bool {
+        self.get(block).opacity == Opacity::Opaque
+    }
+    // blocks counted by the heightmaps
+    pub fn is_solid(&self, block: Block) -> bool {
+        self.get(block).collision
+    }
+    pub fn opacity(&self, block: Block) -> Opacity {
+        self.get(block).opacity
+    }
+    // whether the face of the block touching the neighbor is drawn, faces between cutout
+    // blocks are kept so leaves can be seen through, faces between translucent blocks of
+    // the same type are removed so glass looks like a single pane
+    pub fn face_visible(&self, block: Block, neighbor: Block) -> bool {
+        match (self.opacity(block), self.opacity(neighbor)) {
+            (Opacity::Invisible, _) | (_, Opacity::Opaque) => false,
+            (Opacity::Translucent, Opacity::Translucent) => block.block_type != neighbor.block_type,
+            _ => true,
+        }
+    }
+    pub fn face_texture(&self, block: Block, dir: usize) -> u32 {
+        self.face_textures
+            .get(block.block_type as usize)
+            .map_or(0, |faces| faces[dir])
+    }
+    // texture names, in the order of the layers of the texture array
+    pub fn textures(&self) -> &[String] {
+        &self.textures
+    }
+}
+pub fn texture_path(texture: &str) -> String {
+    format!("textures/{}.png", texture)
+}
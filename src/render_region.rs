@@ -1,4 +1,4 @@
-use crate::mesh::Face;
+use crate::mesher::Face;
 use glam::{ivec3, vec3, IVec3, Vec3};
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
@@ -38,7 +38,8 @@ pub struct Renderer {
 }
 impl Renderer {
     #[profiling::function]
-    pub async fn new(window: &Window) -> Self {
+    // textures are the names of the layers of the block texture array
+    pub async fn new(window: &Window, textures: &[String]) -> Self {
         let size = window.inner_size();
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
@@ -207,6 +208,7 @@ impl Renderer {
             &config,
             &context_bind_group_layout,
             &depth_bind_group_layout,
+            textures,
         );
         queue.submit(Some(init_encoder.finish()));
         Self {
@@ -356,6 +358,12 @@ impl Renderer {
                 label: Some("Chunk Render Encoder"),
             });
 
+        self.chunk_renderer.sort_translucent(
+            camera.pos,
+            self.player_chunk_pos,
+            &self.queue,
+            &self.device,
+        );
         self.chunk_renderer.render_chunks(
             &mut encoder,
             &view,
@@ -0,0 +1,30 @@
+use crate::region::Codec;
+use serde::{Deserialize, Serialize};
+
+// what replaces a saved chunk that can't be decoded
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CorruptChunks {
+    #[default]
+    Regenerate,
+    Empty,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct WorldSettings {
+    pub codec: Codec,
+    #[serde(default)]
+    pub corrupt_chunks: CorruptChunks,
+}
+impl Default for WorldSettings {
+    fn default() -> Self {
+        WorldSettings {
+            codec: Codec::Lz4,
+            corrupt_chunks: CorruptChunks::Regenerate,
+        }
+    }
+}
+impl WorldSettings {
+    pub fn new() -> Self {
+        WorldSettings::default()
+    }
+}
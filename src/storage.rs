@@ -0,0 +1,212 @@
+use crate::chunk::Chunk;
+use crate::region::Region;
+use crate::settings::{CorruptChunks, WorldSettings};
+use glam::{ivec3, IVec3};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+use std::thread;
+use std::thread::JoinHandle;
+
+// loads handled before checking the channel again, so saves and new player positions are not delayed
+const LOAD_BATCH: usize = 64;
+
+pub enum StorageRequest {
+    Load(IVec3),
+    Save(IVec3, Box<Chunk>),
+    // chunk position of the player, loads nearest to it are served first
+    PlayerPos(IVec3),
+    // saves everything still queued and closes the regions
+    Shutdown,
+}
+
+// chunks loaded from the save, None when the chunk has to be generated
+pub type LoadResult = (IVec3, Option<Box<Chunk>>);
+
+fn region_pos(pos: IVec3) -> IVec3 {
+    ivec3(pos.x >> 4, pos.y >> 2, pos.z >> 4)
+}
+
+pub struct StorageService {
+    save_dir: String,
+    settings: WorldSettings,
+    // name of the world generator, written in the headers of new regions
+    generator: String,
+    regions: HashMap<IVec3, Region>,
+    // regions that failed to open, their chunks are generated but never saved
+    unavailable_regions: HashSet<IVec3>,
+    loads: Vec<IVec3>,
+    saves: Vec<(IVec3, Box<Chunk>)>,
+    player_pos: IVec3,
+    result_sender: mpsc::Sender<LoadResult>,
+}
+impl StorageService {
+    pub fn spawn(
+        save_dir: String,
+        settings: WorldSettings,
+        generator: String,
+    ) -> (
+        crossbeam_channel::Sender<StorageRequest>,
+        mpsc::Receiver<LoadResult>,
+        JoinHandle<()>,
+    ) {
+        let (request_sender, request_receiver) = crossbeam_channel::unbounded();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let mut service = StorageService {
+            save_dir,
+            settings,
+            generator,
+            regions: HashMap::new(),
+            unavailable_regions: HashSet::new(),
+            loads: vec![],
+            saves: vec![],
+            player_pos: IVec3::ZERO,
+            result_sender,
+        };
+        let handle = thread::spawn(move || service.run(request_receiver));
+        (request_sender, result_receiver, handle)
+    }
+    fn run(&mut self, receiver: crossbeam_channel::Receiver<StorageRequest>) {
+        let mut compacting = false;
+        loop {
+            // block only when there is nothing left to do
+            let request = if self.idle() && !compacting {
+                Some(receiver.recv().unwrap_or(StorageRequest::Shutdown))
+            } else {
+                match receiver.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(crossbeam_channel::TryRecvError::Empty) => None,
+                    Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                        Some(StorageRequest::Shutdown)
+                    }
+                }
+            };
+            let mut shutdown = false;
+            let mut next = request;
+            while let Some(request) = next {
+                match request {
+                    StorageRequest::Load(pos) => self.loads.push(pos),
+                    StorageRequest::Save(pos, chunk) => self.saves.push((pos, chunk)),
+                    StorageRequest::PlayerPos(pos) => self.player_pos = pos,
+                    StorageRequest::Shutdown => shutdown = true,
+                }
+                next = receiver.try_recv().ok();
+            }
+            self.save_batch();
+            if shutdown {
+                self.close_all();
+                return;
+            }
+            self.load_batch();
+            if self.idle() {
+                compacting = self.maintain();
+            }
+        }
+    }
+    fn idle(&self) -> bool {
+        self.loads.is_empty() && self.saves.is_empty()
+    }
+    fn region(&mut self, pos: IVec3) -> Option<&mut Region> {
+        if !self.regions.contains_key(&pos) && !self.unavailable_regions.contains(&pos) {
+            match Region::new(
+                self.save_dir.clone(),
+                pos,
+                self.settings.codec,
+                &self.generator,
+            ) {
+                Ok(region) => {
+                    self.regions.insert(pos, region);
+                }
+                Err(e) => {
+                    log::error!("{:#}", e);
+                    self.unavailable_regions.insert(pos);
+                }
+            }
+        }
+        self.regions.get_mut(&pos)
+    }
+    // saves are grouped by region so each region file is written in one go
+    #[profiling::function]
+    fn save_batch(&mut self) {
+        let mut saves = std::mem::take(&mut self.saves);
+        saves.sort_by_key(|(pos, _)| {
+            let region_pos = region_pos(*pos);
+            (region_pos.x, region_pos.y, region_pos.z)
+        });
+        for (pos, chunk) in saves {
+            let region_pos = region_pos(pos);
+            let region = match self.region(region_pos) {
+                Some(region) => region,
+                None => continue,
+            };
+            if let Err(e) = region.save_chunk(chunk, pos) {
+                log::error!("chunk {} could not be saved: {}", pos, e);
+            }
+            if region.chunk_count == 0 {
+                self.close(region_pos);
+            }
+        }
+    }
+    // serves the loads nearest to the player first, grouped by region
+    #[profiling::function]
+    fn load_batch(&mut self) {
+        let player_pos = self.player_pos;
+        self.loads
+            .sort_by_key(|pos| -(*pos - player_pos).abs().max_element());
+        let count = self.loads.len().min(LOAD_BATCH);
+        let mut batch = self.loads.split_off(self.loads.len() - count);
+        batch.sort_by_key(|pos| {
+            let region_pos = region_pos(*pos);
+            (region_pos.x, region_pos.y, region_pos.z)
+        });
+        let corrupt_chunks = self.settings.corrupt_chunks;
+        for pos in batch {
+            let loaded = match self.region(region_pos(pos)) {
+                Some(region) => region.load_chunk(pos),
+                None => Ok(None),
+            };
+            let chunk = match loaded {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    log::error!("chunk {} could not be loaded: {}", pos, e);
+                    match corrupt_chunks {
+                        CorruptChunks::Regenerate => None,
+                        CorruptChunks::Empty => Some(Box::new(Chunk::new())),
+                    }
+                }
+            };
+            // the chunk loader is gone, nothing will wait for the remaining loads
+            if self.result_sender.send((pos, chunk)).is_err() {
+                self.loads.clear();
+                return;
+            }
+        }
+    }
+    // compacts and flushes the open regions, returns whether compaction should continue
+    #[profiling::function]
+    fn maintain(&mut self) -> bool {
+        let mut compacting = false;
+        for (pos, region) in self.regions.iter_mut() {
+            match region.compact_step() {
+                Ok(moved) => compacting |= moved,
+                Err(e) => log::error!("region {} could not be compacted: {}", pos, e),
+            }
+            if let Err(e) = region.flush() {
+                log::error!("region {} could not be saved: {}", pos, e);
+            }
+        }
+        compacting
+    }
+    fn close(&mut self, pos: IVec3) {
+        if let Some(mut region) = self.regions.remove(&pos) {
+            if let Err(e) = region.flush() {
+                log::error!("region {} could not be saved: {}", pos, e);
+            }
+        }
+    }
+    fn close_all(&mut self) {
+        let positions: Vec<IVec3> = self.regions.keys().copied().collect();
+        for pos in positions {
+            self.close(pos);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+// assets are looked up next to the executable and in its parent directories, then in the
+// source tree, so the game can be started from any directory
+pub fn asset_path(path: &str) -> PathBuf {
+    let mut candidates = vec![];
+    if let Ok(exe) = std::env::current_exe() {
+        candidates.extend(exe.ancestors().skip(1).map(|dir| dir.join(path)));
+    }
+    candidates.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path));
+    candidates
+        .into_iter()
+        .find(|candidate| candidate.exists())
+        .unwrap_or_else(|| PathBuf::from(path))
+}
@@ -12,7 +12,8 @@ pub struct ThreadPool<T: Send + 'static, U: Send + 'static> {
     running: Arc<AtomicBool>,
 }
 impl<T: Send + 'static, U: Send + 'static> ThreadPool<T, U> {
-    pub fn new(function: fn(T) -> U) -> (mpsc::Receiver<U>, Self) {
+    pub fn new<F: Fn(T) -> U + Send + Sync + 'static>(function: F) -> (mpsc::Receiver<U>, Self) {
+        let function = Arc::new(function);
         let (sender, receiver_thread) = crossbeam_channel::unbounded()
             as (crossbeam_channel::Sender<T>, crossbeam_channel::Receiver<T>);
         let (sender_thread, receiver) = mpsc::channel() as (mpsc::Sender<U>, mpsc::Receiver<U>);
@@ -22,12 +23,13 @@ impl<T: Send + 'static, U: Send + 'static> ThreadPool<T, U> {
             let loc_receiver = receiver_thread.clone();
             let loc_sender = sender_thread.clone();
             let loc_running = running.clone();
+            let loc_function = function.clone();
             thread_handles.push(thread::spawn(move || {
                 while loc_running.load(Ordering::Relaxed) {
                     let input = loc_receiver.try_recv();
                     match input {
                         Ok(input) => {
-                            loc_sender.send(function(input)).unwrap();
+                            loc_sender.send(loc_function(input)).unwrap();
                         }
                         Err(_) => {
                             thread::sleep(Duration::from_millis(20));
@@ -0,0 +1,468 @@
+use crate::block::Block;
+use anyhow::{bail, Context, Result};
+use glam::{ivec3, uvec3, IVec3, UVec3};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs;
+
+// MagicaVoxel limits each model to 256 voxels per axis, bigger selections are split in several models
+const MODEL_SIZE: i32 = 256;
+const VOX_VERSION: u32 = 150;
+// bounds of imported scenes, so a malformed file can't make the model take all the memory
+const MAX_SIZE: i32 = 2048;
+const MAX_BLOCKS: usize = 1 << 26;
+const MAX_TRANSLATION: i32 = 1 << 24;
+
+// block types of the colors used in .vox files, read from a ron file as a list of (block_type, rgba)
+#[derive(Serialize, Deserialize)]
+pub struct VoxMapping {
+    pub colors: Vec<(u16, [u8; 4])>,
+}
+impl Default for VoxMapping {
+    fn default() -> Self {
+        VoxMapping {
+            colors: vec![
+                (1, [96, 160, 64, 255]),
+                (2, [134, 96, 67, 255]),
+                (3, [125, 125, 125, 255]),
+                (4, [150, 74, 60, 255]),
+                (5, [102, 81, 50, 255]),
+                (6, [60, 120, 40, 255]),
+            ],
+        }
+    }
+}
+impl VoxMapping {
+    pub fn new() -> Self {
+        VoxMapping::default()
+    }
+    pub fn load(filename: &str) -> Result<Self> {
+        let text =
+            fs::read_to_string(filename).with_context(|| format!("could not read {}", filename))?;
+        ron::from_str(&text).with_context(|| format!("{} is not valid", filename))
+    }
+    fn color(&self, block_type: u16) -> [u8; 4] {
+        match self.colors.iter().find(|(t, _)| *t == block_type) {
+            Some((_, color)) => *color,
+            None => fallback_color(block_type),
+        }
+    }
+    // exact matches first, then colors generated for unmapped block types, then the nearest color
+    fn block_type(&self, color: [u8; 4]) -> u16 {
+        if let Some((t, _)) = self.colors.iter().find(|(_, c)| c[..3] == color[..3]) {
+            return *t;
+        }
+        if color[2] == FALLBACK_BLUE {
+            return (color[0] as u16) << 8 | color[1] as u16;
+        }
+        let distance = |c: &[u8; 4]| {
+            (0..3)
+                .map(|i| (c[i] as i32 - color[i] as i32).pow(2))
+                .sum::<i32>()
+        };
+        match self.colors.iter().min_by_key(|(_, c)| distance(c)) {
+            Some((t, _)) => *t,
+            None => 1,
+        }
+    }
+}
+
+// block types missing from the mapping are written with their id in the red and green channels
+const FALLBACK_BLUE: u8 = 1;
+fn fallback_color(block_type: u16) -> [u8; 4] {
+    [
+        (block_type >> 8) as u8,
+        block_type as u8,
+        FALLBACK_BLUE,
+        255,
+    ]
+}
+
+// blocks of a box of the world, 0 is empty.
+// MagicaVoxel is z up, its y axis is the engine's -z axis so models are not mirrored
+pub struct VoxModel {
+    pub size: UVec3,
+    pub blocks: Vec<Block>,
+}
+impl VoxModel {
+    pub fn new(size: UVec3) -> Self {
+        VoxModel {
+            size,
+            blocks: vec![Block::new(0); (size.x * size.y * size.z) as usize],
+        }
+    }
+    fn index(&self, pos: UVec3) -> usize {
+        (pos.x + self.size.x * (pos.y + self.size.y * pos.z)) as usize
+    }
+    pub fn get_block(&self, pos: UVec3) -> Block {
+        self.blocks[self.index(pos)]
+    }
+    pub fn set_block(&mut self, pos: UVec3, block: Block) {
+        let index = self.index(pos);
+        self.blocks[index] = block;
+    }
+    fn to_vox(&self, pos: UVec3) -> IVec3 {
+        ivec3(pos.x as i32, (self.size.z - 1 - pos.z) as i32, pos.y as i32)
+    }
+
+    pub fn to_bytes(&self, mapping: &VoxMapping) -> Result<Vec<u8>> {
+        let mut palette: HashMap<u16, u8> = HashMap::new();
+        let mut colors = vec![];
+        let vox_size = ivec3(self.size.x as i32, self.size.z as i32, self.size.y as i32);
+        let tiles = (vox_size + MODEL_SIZE - 1) / MODEL_SIZE;
+        let mut models: Vec<Vec<[u8; 4]>> = vec![vec![]; (tiles.x * tiles.y * tiles.z) as usize];
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let block = self.get_block(uvec3(x, y, z));
+                    if block.block_type == 0 {
+                        continue;
+                    }
+                    let index = match palette.get(&block.block_type) {
+                        Some(index) => *index,
+                        None => {
+                            if colors.len() == 255 {
+                                bail!("a .vox file can't hold more than 255 block types");
+                            }
+                            colors.push(mapping.color(block.block_type));
+                            palette.insert(block.block_type, colors.len() as u8);
+                            colors.len() as u8
+                        }
+                    };
+                    let pos = self.to_vox(uvec3(x, y, z));
+                    let tile = pos / MODEL_SIZE;
+                    let local = pos % MODEL_SIZE;
+                    models[(tile.x + tiles.x * (tile.y + tiles.y * tile.z)) as usize].push([
+                        local.x as u8,
+                        local.y as u8,
+                        local.z as u8,
+                        index,
+                    ]);
+                }
+            }
+        }
+
+        let mut content = vec![];
+        let mut graph = vec![];
+        // root transform and group, then a transform and a shape per model
+        let children: Vec<i32> = (0..models.len() as i32).map(|i| 2 + i * 2).collect();
+        graph.extend(transform_node(0, 1, None));
+        graph.extend(group_node(1, &children));
+        for tz in 0..tiles.z {
+            for ty in 0..tiles.y {
+                for tx in 0..tiles.x {
+                    let i = (tx + tiles.x * (ty + tiles.y * tz)) as usize;
+                    let tile = ivec3(tx, ty, tz);
+                    let size = (vox_size - tile * MODEL_SIZE).min(IVec3::splat(MODEL_SIZE));
+                    let mut size_chunk = vec![];
+                    for axis in size.to_array() {
+                        size_chunk.extend_from_slice(&axis.to_le_bytes());
+                    }
+                    content.extend(chunk(b"SIZE", &size_chunk, &[]));
+                    let mut xyzi = (models[i].len() as u32).to_le_bytes().to_vec();
+                    for voxel in &models[i] {
+                        xyzi.extend_from_slice(voxel);
+                    }
+                    content.extend(chunk(b"XYZI", &xyzi, &[]));
+                    // MagicaVoxel places the center of a model at its translation
+                    let translation = tile * MODEL_SIZE + size / 2;
+                    graph.extend(transform_node(
+                        2 + i as i32 * 2,
+                        3 + i as i32 * 2,
+                        Some(translation),
+                    ));
+                    graph.extend(shape_node(3 + i as i32 * 2, i as i32));
+                }
+            }
+        }
+        content.extend(graph);
+        let mut rgba = vec![];
+        for i in 0..256 {
+            rgba.extend_from_slice(colors.get(i).unwrap_or(&[0, 0, 0, 255]));
+        }
+        content.extend(chunk(b"RGBA", &rgba, &[]));
+
+        let mut buffer = b"VOX ".to_vec();
+        buffer.extend_from_slice(&VOX_VERSION.to_le_bytes());
+        buffer.extend(chunk(b"MAIN", &[], &content));
+        Ok(buffer)
+    }
+
+    pub fn from_bytes(buffer: &[u8], mapping: &VoxMapping) -> Result<Self> {
+        if buffer.len() < 8 || &buffer[..4] != b"VOX " {
+            bail!("not a .vox file");
+        }
+        let mut reader = Reader { buffer, pos: 8 };
+        let (id, _, _) = reader.chunk_header()?;
+        if &id != b"MAIN" {
+            bail!("missing MAIN chunk");
+        }
+        let mut sizes = vec![];
+        let mut models: Vec<Vec<[u8; 4]>> = vec![];
+        let mut colors = vec![[0u8; 4]; 256];
+        let mut nodes: HashMap<i32, Node> = HashMap::new();
+        while reader.pos < buffer.len() {
+            let (id, content_len, children_len) = reader.chunk_header()?;
+            let end = reader.pos.saturating_add(content_len);
+            if end.saturating_add(children_len) > buffer.len() {
+                bail!("chunk {} is truncated", String::from_utf8_lossy(&id));
+            }
+            match &id {
+                b"SIZE" => {
+                    let size = ivec3(reader.i32()?, reader.i32()?, reader.i32()?);
+                    if size.cmplt(IVec3::ZERO).any() || size.cmpgt(IVec3::splat(MODEL_SIZE)).any() {
+                        bail!("invalid model size {}", size);
+                    }
+                    sizes.push(size);
+                }
+                b"XYZI" => {
+                    let count = reader.count()?;
+                    let len = count.checked_mul(4).context("too many voxels in a model")?;
+                    let voxels = reader.bytes(len)?;
+                    models.push(
+                        voxels
+                            .chunks_exact(4)
+                            .map(|v| v.try_into().unwrap())
+                            .collect(),
+                    );
+                }
+                b"RGBA" => {
+                    // palette entry i is the color of index i + 1
+                    for i in 0..255 {
+                        colors[i + 1] = reader.bytes(4)?.try_into().unwrap();
+                    }
+                }
+                b"nTRN" => {
+                    let id = reader.i32()?;
+                    reader.dict()?;
+                    let child = reader.i32()?;
+                    reader.i32()?;
+                    reader.i32()?;
+                    let frames = reader.count()?;
+                    let mut translation = IVec3::ZERO;
+                    for frame in 0..frames {
+                        let attributes = reader.dict()?;
+                        if frame != 0 {
+                            continue;
+                        }
+                        if let Some(t) = attributes.get("_t") {
+                            let t: Vec<i32> = t.split(' ').filter_map(|c| c.parse().ok()).collect();
+                            if t.len() == 3 {
+                                translation = ivec3(t[0], t[1], t[2]).clamp(
+                                    IVec3::splat(-MAX_TRANSLATION),
+                                    IVec3::splat(MAX_TRANSLATION),
+                                );
+                            }
+                        }
+                        if attributes.contains_key("_r") {
+                            log::warn!("rotations of .vox models are ignored");
+                        }
+                    }
+                    nodes.insert(id, Node::Transform(child, translation));
+                }
+                b"nGRP" => {
+                    let id = reader.i32()?;
+                    reader.dict()?;
+                    let count = reader.count()?;
+                    let mut children = vec![];
+                    for _ in 0..count {
+                        children.push(reader.i32()?);
+                    }
+                    nodes.insert(id, Node::Group(children));
+                }
+                b"nSHP" => {
+                    let id = reader.i32()?;
+                    reader.dict()?;
+                    let count = reader.count()?;
+                    let mut shapes = vec![];
+                    for _ in 0..count {
+                        shapes.push(reader.i32()?);
+                        reader.dict()?;
+                    }
+                    nodes.insert(id, Node::Shape(shapes));
+                }
+                _ => {}
+            }
+            reader.pos = end + children_len;
+        }
+        if sizes.len() != models.len() {
+            bail!("{} model sizes for {} models", sizes.len(), models.len());
+        }
+
+        // voxels in MagicaVoxel coordinates with their color index
+        let mut placed = vec![];
+        let mut place = |model: usize, translation: IVec3, centered: bool| -> Result<()> {
+            let offset = if centered {
+                translation - sizes[model] / 2
+            } else {
+                translation
+            };
+            if placed.len() + models[model].len() > MAX_BLOCKS {
+                bail!("the scene has more than {} voxels", MAX_BLOCKS);
+            }
+            for voxel in &models[model] {
+                placed.push((
+                    offset + ivec3(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32),
+                    voxel[3],
+                ));
+            }
+            Ok(())
+        };
+        if nodes.contains_key(&0) {
+            // each node has a single parent, a node reached twice is part of a cycle
+            let mut visited = HashSet::new();
+            let mut stack = vec![(0, IVec3::ZERO)];
+            while let Some((id, translation)) = stack.pop() {
+                if !visited.insert(id) {
+                    bail!("scene node {} is reached more than once", id);
+                }
+                match nodes.get(&id) {
+                    Some(Node::Transform(child, t)) => {
+                        let translation = translation + *t;
+                        // translations stay far from the bounds of i32 so positions can't overflow
+                        if translation.abs().cmpgt(IVec3::splat(MAX_TRANSLATION)).any() {
+                            bail!("scene node {} is translated out of range", id);
+                        }
+                        stack.push((*child, translation))
+                    }
+                    Some(Node::Group(children)) => {
+                        stack.extend(children.iter().map(|child| (*child, translation)))
+                    }
+                    Some(Node::Shape(shapes)) => {
+                        for shape in shapes {
+                            if (*shape as usize) < models.len() {
+                                place(*shape as usize, translation, true)?;
+                            }
+                        }
+                    }
+                    None => bail!("missing scene node {}", id),
+                }
+            }
+        } else {
+            for model in 0..models.len() {
+                place(model, IVec3::ZERO, false)?;
+            }
+        }
+        if placed.is_empty() {
+            return Ok(VoxModel::new(UVec3::ZERO));
+        }
+
+        let engine_pos = |pos: IVec3| ivec3(pos.x, pos.z, -pos.y);
+        let mut min = IVec3::MAX;
+        let mut max = IVec3::MIN;
+        for (pos, _) in &placed {
+            min = min.min(engine_pos(*pos));
+            max = max.max(engine_pos(*pos));
+        }
+        let size = max - min + 1;
+        if size.cmpgt(IVec3::splat(MAX_SIZE)).any()
+            || size.x as usize * size.y as usize * size.z as usize > MAX_BLOCKS
+        {
+            bail!("the scene is too big to import, its size is {}", size);
+        }
+        let mut model = VoxModel::new(size.as_uvec3());
+        for (pos, index) in placed {
+            let block_type = mapping.block_type(colors[index as usize]);
+            model.set_block((engine_pos(pos) - min).as_uvec3(), Block::new(block_type));
+        }
+        Ok(model)
+    }
+}
+
+enum Node {
+    Transform(i32, IVec3),
+    Group(Vec<i32>),
+    Shape(Vec<i32>),
+}
+
+fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+    let mut buffer = id.to_vec();
+    buffer.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&(children.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(content);
+    buffer.extend_from_slice(children);
+    buffer
+}
+fn dict(entries: &[(&str, String)]) -> Vec<u8> {
+    let mut buffer = (entries.len() as u32).to_le_bytes().to_vec();
+    for (key, value) in entries {
+        for text in [key.as_bytes(), value.as_bytes()] {
+            buffer.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(text);
+        }
+    }
+    buffer
+}
+fn transform_node(id: i32, child: i32, translation: Option<IVec3>) -> Vec<u8> {
+    let mut content = id.to_le_bytes().to_vec();
+    content.extend(dict(&[]));
+    content.extend_from_slice(&child.to_le_bytes());
+    // reserved id, layer and frame count
+    content.extend_from_slice(&(-1i32).to_le_bytes());
+    content.extend_from_slice(&0i32.to_le_bytes());
+    content.extend_from_slice(&1i32.to_le_bytes());
+    match translation {
+        Some(t) => content.extend(dict(&[("_t", format!("{} {} {}", t.x, t.y, t.z))])),
+        None => content.extend(dict(&[])),
+    }
+    chunk(b"nTRN", &content, &[])
+}
+fn group_node(id: i32, children: &[i32]) -> Vec<u8> {
+    let mut content = id.to_le_bytes().to_vec();
+    content.extend(dict(&[]));
+    content.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    for child in children {
+        content.extend_from_slice(&child.to_le_bytes());
+    }
+    chunk(b"nGRP", &content, &[])
+}
+fn shape_node(id: i32, model: i32) -> Vec<u8> {
+    let mut content = id.to_le_bytes().to_vec();
+    content.extend(dict(&[]));
+    content.extend_from_slice(&1i32.to_le_bytes());
+    content.extend_from_slice(&model.to_le_bytes());
+    content.extend(dict(&[]));
+    chunk(b"nSHP", &content, &[])
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.buffer.len() - self.pos {
+            bail!("unexpected end of file");
+        }
+        self.pos += len;
+        Ok(&self.buffer[self.pos - len..self.pos])
+    }
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    // lengths and counts are stored as i32
+    fn count(&mut self) -> Result<usize> {
+        let count = self.i32()?;
+        if count < 0 {
+            bail!("negative length {}", count);
+        }
+        Ok(count as usize)
+    }
+    fn chunk_header(&mut self) -> Result<([u8; 4], usize, usize)> {
+        let id = self.bytes(4)?.try_into().unwrap();
+        Ok((id, self.count()?, self.count()?))
+    }
+    fn string(&mut self) -> Result<String> {
+        let len = self.count()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+    fn dict(&mut self) -> Result<HashMap<String, String>> {
+        let count = self.count()?;
+        let mut dict = HashMap::new();
+        for _ in 0..count {
+            dict.insert(self.string()?, self.string()?);
+        }
+        Ok(dict)
+    }
+}
@@ -1,46 +1,123 @@
 use std::sync::mpsc;
+use crate::biome::BiomeDefinition;
 use crate::block::Block;
+use crate::registry::BlockRegistry;
 use crate::camera::Camera;
 use crate::chunk_loader::*;
 use crate::chunk_map::ChunkMap;
-use crate::mesh::*;
+use crate::mesher::{self, ChunkMesh, Lod};
 use crate::renderer::*;
-use crate::util::direction::*;
-use crate::chunk::Chunk;
+use crate::chunk::{neighborhood, Chunk};
+use crate::direction::Direction;
+use crate::generator::{self, WorldGenerator};
+use crate::vox::{VoxMapping, VoxModel};
+use crate::world_meta::{timestamp, WorldMeta};
+use crate::util::assets::asset_path;
 use crate::util::threadpool::ThreadPool;
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
+use anyhow::Result;
 use glam::{ivec3, uvec3, IVec3, UVec3, Vec3};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
 
-const TEXTURE_INDEX: [[u32; 6]; 6] = [
-    [0, 0, 0, 0, 2, 1],
-    [2, 2, 2, 2, 2, 2],
-    [3, 3, 3, 3, 3, 3],
-    [4, 4, 4, 4, 4, 4],
-    [5, 5, 5, 5, 6, 6],
-    [7 ,7 ,7 ,7 ,7 ,7],
-];
-const FACES_LIGHT: [f32; 6] = [0.4, 0.4, 0.7, 0.7, 0.1, 1.0];
+// ticks between two writes of the world metadata
+const META_SAVE_INTERVAL: u32 = 600;
+// horizontal distances in chunks from the player from which the chunks are meshed
+// from cells of 2, 4 and 8 blocks
+const LOD_DISTANCES: [i32; 3] = [8, 14, 20];
+
+// the chunk with its neighbors, whether the faces are merged and the level of detail of the chunk
+type MeshJob = (IVec3, [Arc<Chunk>; 27], bool, Lod);
+
+fn lod_level(rel_pos: IVec3) -> u32 {
+    let dist = rel_pos.x.abs().max(rel_pos.z.abs());
+    LOD_DISTANCES.iter().filter(|start| dist >= **start).count() as u32
+}
+// the level is the same along a column so seams are only on the sides
+fn lod(pos: IVec3, player_pos: IVec3) -> Lod {
+    let level = lod_level(pos - player_pos);
+    let mut seams = [false; 4];
+    for (dir, seam) in seams.iter_mut().enumerate() {
+        let neighbor = pos + Direction { id: dir as u8 }.get_norm();
+        *seam = lod_level(neighbor - player_pos) != level;
+    }
+    Lod { level, seams }
+}
 
 pub struct World {
     pub chunk_map: ChunkMap,
+    pub meta: WorldMeta,
+    pub registry: Arc<BlockRegistry>,
+    save_dir: String,
+    ticks: u32,
     chunk_updates: AHashSet<IVec3>,
     chunk_loader: ChunkLoader,
-    threadpool:ThreadPool<(IVec3,[Box<Chunk>;27]),(IVec3, [Vec<Face>; 6])>,
-    threadpool_receiver: mpsc::Receiver<(IVec3, [Vec<Face>; 6])>,
+    generator: Arc<dyn WorldGenerator>,
+    threadpool:ThreadPool<MeshJob,(IVec3, ChunkMesh)>,
+    // stands for the missing neighbors of the meshed chunks
+    empty_chunk: Arc<Chunk>,
+    threadpool_receiver: mpsc::Receiver<(IVec3, ChunkMesh)>,
+    greedy_meshing: bool,
+    player_pos: IVec3,
+    // level of detail of the last mesh of each chunk
+    lods: AHashMap<IVec3, Lod>,
 }
 
 impl World {
-    pub fn new() -> World {
-        let (threadpool_receiver,threadpool) = ThreadPool::new(|chunks:(IVec3,[Box<Chunk>;27])|{
-            return (chunks.0,World::create_mesh(chunks.1));
+    pub fn new(save_dir: String) -> Result<World> {
+        let mut meta = WorldMeta::load(&save_dir)?;
+        let registry = Arc::new(BlockRegistry::load(&asset_path("blocks.ron"), &mut meta.blocks)?);
+        // the ids given to new block types must be kept before any chunk uses them
+        meta.save(&save_dir)?;
+        let generator: Arc<dyn WorldGenerator> = Arc::from(generator::create_generator(
+            &meta.generator,
+            meta.generator_version,
+            meta.seed,
+            &registry,
+        )?);
+        let mesh_registry = registry.clone();
+        let (threadpool_receiver,threadpool) = ThreadPool::new(move |chunks:MeshJob|{
+            let mut mesh = mesher::create_lod_mesh(&chunks.1, &mesh_registry, chunks.3);
+            // faces of cells are already larger than a block
+            if chunks.2 && chunks.3.level == 0 {
+                mesher::merge_faces(&mut mesh);
+            }
+            return (chunks.0,mesh);
         });
-        World {
+        Ok(World {
             chunk_map: ChunkMap::new(),
-            chunk_loader: ChunkLoader::new(),
+            chunk_loader: ChunkLoader::new(save_dir.clone(), meta.settings, generator.clone(), registry.clone()),
+            generator,
+            meta,
+            registry,
+            save_dir,
+            ticks: 0,
             chunk_updates: AHashSet::new(),
             threadpool,
             threadpool_receiver,
-        }
+            empty_chunk: Arc::new(Chunk::new()),
+            greedy_meshing: true,
+            player_pos: IVec3::ZERO,
+            lods: AHashMap::new(),
+        })
+    }
+    // biome of the column for gameplay and tinting, None when the generator has no biomes
+    pub fn biome(&self, x: i32, z: i32) -> Option<&'static BiomeDefinition> {
+        self.generator.biome(x, z)
+    }
+    // height of the eyes of a player standing on the column,
+    // None until the chunk above its highest solid block is loaded
+    pub fn spawn_height(&self, x: i32, z: i32) -> Option<i32> {
+        let height = self.chunk_map.height(x, z)?;
+        self.chunk_map.get_chunk(ivec3(x >> 5, (height >> 5) + 1, z >> 5))?;
+        Some(height + 2)
+    }
+    // switches between merged and single faces to compare them, every chunk is meshed again
+    pub fn toggle_greedy_meshing(&mut self) {
+        self.greedy_meshing = !self.greedy_meshing;
+        log::info!("greedy meshing {}", if self.greedy_meshing { "on" } else { "off" });
+        self.chunk_updates.extend(self.chunk_map.hash_map.keys());
     }
     #[profiling::function]
     fn add_chunks(&mut self) {
@@ -48,7 +125,7 @@ impl World {
             let chunk_result = self.chunk_loader.try_get_chunk();
             match chunk_result {
                 Some(chunk) => {
-                    self.chunk_map.hash_map.insert(chunk.0, chunk.1);
+                    self.chunk_map.insert(chunk.0, chunk.1);
                     let mut surrounded = [[[true; 3]; 3]; 3];
                     for x in -2..3{
                         for y in -2..3{
@@ -81,8 +158,8 @@ impl World {
     }
     #[profiling::function]
     fn unload_chunks(&mut self, player_pos: IVec3, renderer: &mut Renderer) {
-        let unloaded = self.chunk_map.hash_map.extract_if(|pos, _| {
-            let rel_pos = *pos - player_pos;
+        let unloaded = self.chunk_map.unload(|pos| {
+            let rel_pos = pos - player_pos;
             rel_pos.x < -RENDER_DIST
                 || rel_pos.x > RENDER_DIST
                 || rel_pos.y < -RENDER_DIST_HEIGHT
@@ -94,6 +171,7 @@ impl World {
             renderer
                 .chunk_renderer
                 .remove_chunk(i.0, &mut renderer.queue);
+            self.lods.remove(&i.0);
             self.chunk_loader.save(i);
         }
     }
@@ -104,22 +182,44 @@ impl World {
             camera.pos.y.floor() as i32 >> 5,
             camera.pos.z.floor() as i32 >> 5,
         );
+        if player_pos != self.player_pos {
+            self.player_pos = player_pos;
+            // chunks crossing the distance of a level are meshed again
+            let changed: Vec<IVec3> = self
+                .lods
+                .iter()
+                .filter(|(pos, old_lod)| lod(**pos, player_pos) != **old_lod)
+                .map(|(pos, _)| *pos)
+                .collect();
+            self.chunk_updates.extend(changed);
+        }
         self.unload_chunks(player_pos, renderer);
         self.chunk_loader.tick(&self.chunk_map, player_pos);
         self.add_chunks();
+        self.meta.player = camera.player_state();
+        self.ticks += 1;
+        if self.ticks % META_SAVE_INTERVAL == 0 {
+            self.save_meta();
+        }
+    }
+    // the game goes on when the metadata can't be written, the last save is kept
+    fn save_meta(&mut self) {
+        if let Err(e) = self.meta.save(&self.save_dir) {
+            log::error!("world metadata could not be saved: {:#}", e);
+        }
     }
     #[profiling::function]
     pub fn update_display(&mut self, renderer: &mut Renderer) {
+        let empty_chunk = &self.empty_chunk;
+        let chunk_map = &self.chunk_map;
+        let player_pos = self.player_pos;
         for pos in self.chunk_updates.drain(){
-            let mut chunks:[Box<Chunk>;27] = std::array::from_fn(|_| Box::new(Chunk::new()));
-            for z in -1..2{
-                for y in -1..2{
-                    for x in -1..2 {
-                        chunks[(x+1 + 3*(y+1) + 9*(z+1)) as usize] = self.chunk_map.get_chunk(pos+ivec3(x,y,z)).unwrap_or(&Box::new(Chunk::new())).clone();
-                    }
-                }
-            }
-            self.threadpool.send((pos,chunks)).unwrap();
+            // the job gets its own handles, edits made meanwhile copy the chunks they change
+            let mut neighbors = neighborhood(pos);
+            let chunks:[Arc<Chunk>;27] = std::array::from_fn(|_| chunk_map.get_chunk(neighbors.next().unwrap()).unwrap_or(empty_chunk).clone());
+            let lod = lod(pos, player_pos);
+            self.lods.insert(pos, lod);
+            self.threadpool.send((pos,chunks,self.greedy_meshing,lod)).unwrap();
         }
         loop {
             if let Ok(mut mesh)=self.threadpool_receiver.try_recv() {
@@ -140,143 +240,6 @@ impl World {
             }
         }
     }
-    fn add_face(
-        chunks:&[Box<Chunk>;27],
-        storage: &mut [Vec<Face>; 6],
-        pos: UVec3,
-        dir: Direction,
-        texture: u32,
-    ) {
-        let ao_blocks = [
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(1, 1, 0)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(1, 1, 1)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(0, 1, 1)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(-1, 1, 1)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(-1, 1, 0)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(-1, 1, -1)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(0, 1, -1)))
-                .is_full_block(),
-            World::get_local_block(&chunks, pos.as_ivec3() + dir.transform(ivec3(1, 1, -1)))
-                .is_full_block(),
-        ];
-        let light: [u8; 4] = [
-            ((255 - (ao_blocks[4] || ao_blocks[5] || ao_blocks[6]) as u32 * 180) as f32
-                * FACES_LIGHT[dir.id as usize]) as u8,
-            ((255 - (ao_blocks[2] || ao_blocks[3] || ao_blocks[4]) as u32 * 180) as f32
-                * FACES_LIGHT[dir.id as usize]) as u8,
-            ((255 - (ao_blocks[0] || ao_blocks[1] || ao_blocks[2]) as u32 * 180) as f32
-                * FACES_LIGHT[dir.id as usize]) as u8,
-            ((255 - (ao_blocks[6] || ao_blocks[7] || ao_blocks[0]) as u32 * 180) as f32
-                * FACES_LIGHT[dir.id as usize]) as u8,
-        ];
-        storage[dir.id as usize].push(Face {
-            pos_dir_tex: (pos.x & 63)
-                | ((pos.y & 63) << 6)
-                | ((pos.z & 63) << 12)
-                | ((dir.id as u32 & 7) << 18)
-                | ((texture & 2047) << 21),
-            light,
-        })
-    }
-    fn get_local_block(chunks: &[Box<Chunk>;27],pos:IVec3)->Block{
-        let chunk_pos:IVec3 = (pos>>5) + ivec3(1,1,1);
-        return chunks[(chunk_pos.x + 3*chunk_pos.y + 9*chunk_pos.z) as usize].get_block((pos&31).as_uvec3())
-    }
-    #[profiling::function]
-    pub fn create_mesh(chunks: [Box<Chunk>;27]) -> [Vec<Face>; 6] {
-        let mut storage: [Vec<Face>; 6] = Default::default();
-        for y in 0..32 {
-            for z in 0..32 {
-                for x in 0..32 {
-                    let block1 = chunks[13].get_block(uvec3(x, y, z));
-                    let mut block2: Block = if x < 31 {
-                        chunks[13].get_block(uvec3(x + 1, y, z))
-                    } else {
-                        chunks[13+1].get_block(uvec3(0, y, z))
-                    };
-                    let b1 = block1.is_full_block();
-                    let mut b2 = block2.is_full_block();
-                    if b1 != b2 {
-                        if b1 == true {
-                            World::add_face(
-                                &chunks,
-                                &mut storage,
-                                uvec3(x, y, z),
-                                Direction { id: 1 },
-                                TEXTURE_INDEX[block1.block_type as usize - 1][1],
-                            );
-                        } else {
-                            World::add_face(
-                                &chunks,
-                                &mut storage,
-                                uvec3(x + 1, y, z),
-                                Direction { id: 0 },
-                                TEXTURE_INDEX[block2.block_type as usize - 1][0],
-                            );
-                        }
-                    }
-                    block2 = if z < 31 {
-                        chunks[13].get_block(uvec3(x, y, z + 1))
-                    } else {
-                        chunks[13+9].get_block(uvec3(x, y, 0))
-                    };
-                    b2 = block2.is_full_block();
-                    if b1 != b2 {
-                        if b1 == true {
-                            World::add_face(
-                                &chunks,
-                                &mut storage,
-                                uvec3(x, y, z),
-                                Direction { id: 3 },
-                                TEXTURE_INDEX[block1.block_type as usize - 1][3],
-                            );
-                        } else {
-                            World::add_face(
-                                &chunks,
-                                &mut storage,
-                                uvec3(x, y, z + 1),
-                                Direction { id: 2 },
-                                TEXTURE_INDEX[block2.block_type as usize - 1][2],
-                            );
-                        }
-                    }
-
-                    block2 = if y < 31 {
-                        chunks[13].get_block(uvec3(x, y + 1, z))
-                    } else {
-                        chunks[13+3].get_block(uvec3(x, 0, z))
-                    };
-                    b2 = block2.is_full_block();
-                    if b1 != b2 {
-                        if b1 == true {
-                            World::add_face(
-                                &chunks,
-                                &mut storage,
-                                uvec3(x, y, z),
-                                Direction { id: 5 },
-                                TEXTURE_INDEX[block1.block_type as usize - 1][5],
-                            );
-                        } else {
-                            World::add_face(
-                                &chunks,
-                                &mut storage,
-                                uvec3(x, y + 1, z),
-                                Direction { id: 4 },
-                                TEXTURE_INDEX[block2.block_type as usize - 1][4],
-                            );
-                        }
-                    }
-                }
-            }
-        }
-        storage
-    }
     #[profiling::function]
     pub fn set_block(&mut self, pos: IVec3, value: Block) {
         let chunk_pos = ivec3(pos.x >> 5, pos.y >> 5, pos.z >> 5);
@@ -304,6 +267,106 @@ impl World {
                 self.chunk_updates.insert(chunk_pos + ivec3(0, 0, 1));
             }
             chunk.set_block(loc_pos, value);
+            chunk.update_height(loc_pos, &self.registry);
+        }
+    }
+    // blocks of the box between two corners, both included
+    #[profiling::function]
+    pub fn export_vox(&self, a: IVec3, b: IVec3) -> VoxModel {
+        let min = a.min(b);
+        let mut model = VoxModel::new((a.max(b) - min + 1).as_uvec3());
+        for z in 0..model.size.z {
+            for y in 0..model.size.y {
+                for x in 0..model.size.x {
+                    let pos = uvec3(x, y, z);
+                    model.set_block(pos, self.chunk_map.get_block(min + pos.as_ivec3()));
+                }
+            }
+        }
+        model
+    }
+    // schedules every chunk whose mesh can see a block of the box
+    fn box_updated(&mut self, min: IVec3, max: IVec3) {
+        let min_chunk = ivec3((min.x - 1) >> 5, (min.y - 1) >> 5, (min.z - 1) >> 5);
+        let max_chunk = ivec3((max.x + 1) >> 5, (max.y + 1) >> 5, (max.z + 1) >> 5);
+        for z in min_chunk.z..=max_chunk.z {
+            for y in min_chunk.y..=max_chunk.y {
+                for x in min_chunk.x..=max_chunk.x {
+                    self.chunk_updates.insert(ivec3(x, y, z));
+                }
+            }
+        }
+    }
+    // the bulk operations work on the box between two corners, both included
+    pub fn fill(&mut self, a: IVec3, b: IVec3, block: Block) {
+        self.chunk_map.fill(a.min(b), a.max(b), block, &self.registry);
+        self.box_updated(a.min(b), a.max(b));
+    }
+    pub fn replace(&mut self, a: IVec3, b: IVec3, from: Block, to: Block) {
+        self.chunk_map.replace(a.min(b), a.max(b), from, to, &self.registry);
+        self.box_updated(a.min(b), a.max(b));
+    }
+    pub fn apply<F: FnMut(IVec3, Block) -> Block>(&mut self, a: IVec3, b: IVec3, f: F) {
+        self.chunk_map.apply(a.min(b), a.max(b), &self.registry, f);
+        self.box_updated(a.min(b), a.max(b));
+    }
+    // dest is the lowest corner of the copy
+    pub fn copy(&mut self, a: IVec3, b: IVec3, dest: IVec3) {
+        self.chunk_map.copy(a.min(b), a.max(b), dest, &self.registry);
+        self.box_updated(dest, dest + (a - b).abs());
+    }
+    // empty voxels of the model leave the world unchanged
+    #[profiling::function]
+    pub fn import_vox(&mut self, model: &VoxModel, pos: IVec3) {
+        if model.size.cmpeq(UVec3::ZERO).any() {
+            return;
+        }
+        self.apply(pos, pos + model.size.as_ivec3() - 1, |block_pos, block| {
+            let voxel = model.get_block((block_pos - pos).as_uvec3());
+            if voxel.block_type != 0 {
+                voxel
+            } else {
+                block
+            }
+        });
+    }
+    // .vox files are exchanged through the vox directory of the world,
+    // colors.ron in it overrides the default color of each block type
+    fn vox_mapping(&self) -> VoxMapping {
+        let filename = self.save_dir.clone() + "/vox/colors.ron";
+        if !Path::new(&filename).exists() {
+            return VoxMapping::new();
+        }
+        VoxMapping::load(&filename).unwrap_or_else(|e| {
+            log::error!("{:#}", e);
+            VoxMapping::new()
+        })
+    }
+    pub fn export_selection(&self, a: IVec3, b: IVec3) {
+        let filename = format!("{}/vox/export-{}.vox", self.save_dir, timestamp());
+        let result = self
+            .export_vox(a, b)
+            .to_bytes(&self.vox_mapping())
+            .and_then(|bytes| {
+                fs::create_dir_all(self.save_dir.clone() + "/vox")?;
+                Ok(fs::write(&filename, bytes)?)
+            });
+        match result {
+            Ok(()) => log::info!("exported {} to {} to {}", a, b, filename),
+            Err(e) => log::error!("export failed: {:#}", e),
+        }
+    }
+    pub fn import_model(&mut self, pos: IVec3) {
+        let filename = self.save_dir.clone() + "/vox/import.vox";
+        let result = fs::read(&filename)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| VoxModel::from_bytes(&bytes, &self.vox_mapping()));
+        match result {
+            Ok(model) => {
+                self.import_vox(&model, pos);
+                log::info!("imported {} at {}", filename, pos);
+            }
+            Err(e) => log::error!("{}: import failed: {:#}", filename, e),
         }
     }
     #[profiling::function]
@@ -345,7 +408,7 @@ impl World {
             side_dist_z = (block_pos.z as f32 - pos.z + 1.0) * delta_dist_z;
         }
         let mut i = 0;
-        while !self.chunk_map.get_block(block_pos).is_full_block() && i < 200 {
+        while !self.registry.get(self.chunk_map.get_block(block_pos)).collision && i < 200 {
             i += 1;
             if side_dist_x < side_dist_y && side_dist_x < side_dist_z {
                 side_dist_x += delta_dist_x;
@@ -395,8 +458,9 @@ impl World {
 
 impl Drop for World {
     fn drop(&mut self) {
-        for i in self.chunk_map.hash_map.drain() {
+        for i in self.chunk_map.drain() {
             self.chunk_loader.save(i);
         }
+        self.save_meta();
     }
 }
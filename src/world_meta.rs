@@ -0,0 +1,119 @@
+use crate::generator::{NoiseGenerator, DEFAULT_GENERATOR};
+use crate::registry::LEGACY_BLOCKS;
+use crate::settings::WorldSettings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// seed of the terrain of worlds created before it was saved, by version 1 of the noise generator
+pub const LEGACY_SEED: u64 = 132487 << 32 | 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayerState {
+    pub pos: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub speed: f32,
+    // false until the player of a new world is placed on the terrain,
+    // players saved before it existed keep their position
+    #[serde(default = "already_spawned")]
+    pub spawned: bool,
+}
+fn already_spawned() -> bool {
+    true
+}
+impl Default for PlayerState {
+    fn default() -> Self {
+        PlayerState {
+            pos: [10.0, 50.0, 10.0],
+            yaw: 0.0,
+            pitch: 0.0,
+            speed: 0.1,
+            spawned: false,
+        }
+    }
+}
+impl PlayerState {
+    pub fn new() -> Self {
+        PlayerState::default()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorldMeta {
+    pub seed: u64,
+    pub generator: String,
+    pub generator_version: u32,
+    pub player: PlayerState,
+    pub created: u64,
+    pub last_played: u64,
+    pub settings: WorldSettings,
+    // names of the block types by id, filled by the block registry
+    #[serde(default = "legacy_blocks")]
+    pub blocks: Vec<String>,
+}
+fn legacy_blocks() -> Vec<String> {
+    LEGACY_BLOCKS
+        .iter()
+        .map(|name| String::from(*name))
+        .collect()
+}
+// 0 when the clock is set before 1970
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+impl WorldMeta {
+    pub fn new(seed: u64) -> Self {
+        WorldMeta {
+            seed,
+            generator: String::from(DEFAULT_GENERATOR),
+            generator_version: NoiseGenerator::VERSION,
+            player: PlayerState::new(),
+            created: timestamp(),
+            last_played: timestamp(),
+            settings: WorldSettings::new(),
+            blocks: vec![],
+        }
+    }
+    pub fn load(save_dir: &str) -> Result<Self> {
+        let filename = String::from(save_dir) + "/world.ron";
+        let meta = match fs::read_to_string(&filename) {
+            Ok(text) => ron::from_str::<WorldMeta>(&text)
+                .with_context(|| format!("{} is not valid", filename))?,
+            Err(_) => {
+                // worlds saved before the metadata file existed keep their blocks,
+                // their terrain comes from the first version of the noise generator
+                if Path::new(&(String::from(save_dir) + "/region")).exists() {
+                    let mut meta = WorldMeta::new(LEGACY_SEED);
+                    meta.generator_version = 1;
+                    meta.blocks = legacy_blocks();
+                    meta
+                } else {
+                    WorldMeta::new(
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_nanos() as u64,
+                    )
+                }
+            }
+        };
+        Ok(meta)
+    }
+    // written next to world.ron then moved over it, so a failed save keeps the previous one
+    pub fn save(&mut self, save_dir: &str) -> Result<()> {
+        self.last_played = timestamp();
+        fs::create_dir_all(save_dir).with_context(|| format!("could not create {}", save_dir))?;
+        let filename = String::from(save_dir) + "/world.ron";
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(filename.clone() + ".tmp", text)
+            .with_context(|| format!("could not write {}.tmp", filename))?;
+        fs::rename(filename.clone() + ".tmp", &filename)
+            .with_context(|| format!("could not replace {}", filename))
+    }
+}
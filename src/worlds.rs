@@ -0,0 +1,161 @@
+use crate::generator::{self, GENERATORS};
+use crate::world_meta::WorldMeta;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const APP_NAME: &str = "rust_voxel_engine";
+// worlds used to be saved in this directory relative to the working directory
+pub const LEGACY_SAVE_DIR: &str = "save";
+
+// read from <config dir>/rust_voxel_engine/config.ron, command line options take precedence
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub saves_dir: String,
+    pub world: String,
+}
+impl Default for Config {
+    fn default() -> Self {
+        let saves_dir = match dirs::data_dir() {
+            Some(dir) => dir.join(APP_NAME).join("saves"),
+            None => PathBuf::from("saves"),
+        };
+        Config {
+            saves_dir: saves_dir.to_string_lossy().into_owned(),
+            world: String::from("world"),
+        }
+    }
+}
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(APP_NAME).join("config.ron"))
+    }
+    pub fn load() -> Self {
+        let path = match Config::path() {
+            Some(path) => path,
+            None => return Config::default(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|e| {
+                log::warn!("{} is not valid, using defaults: {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+    pub fn world_dir(&self, name: &str) -> String {
+        world_dir(&self.saves_dir, name)
+    }
+}
+
+// a world name is a single directory of the saves directory
+pub fn check_world_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(['/', '\\']) => Ok(()),
+        _ => bail!("\"{}\" is not a valid world name", name),
+    }
+}
+pub fn world_dir(saves_dir: &str, name: &str) -> String {
+    String::from(saves_dir) + "/" + name
+}
+pub fn is_world(dir: &str) -> bool {
+    Path::new(&(String::from(dir) + "/world.ron")).exists()
+        || Path::new(&(String::from(dir) + "/region")).exists()
+}
+// names of the worlds in the saves directory with their metadata, sorted by name,
+// worlds whose metadata can't be read are listed with the error
+pub fn list_worlds(saves_dir: &str) -> Vec<(String, Result<WorldMeta>)> {
+    let mut worlds = vec![];
+    if let Ok(entries) = fs::read_dir(saves_dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let dir = world_dir(saves_dir, &name);
+            if entry.path().is_dir() && is_world(&dir) {
+                worlds.push((name, WorldMeta::load(&dir)));
+            }
+        }
+    }
+    worlds.sort_by(|a, b| a.0.cmp(&b.0));
+    worlds
+}
+pub fn create_world(dir: &str, seed: u64, generator: &str) -> Result<()> {
+    let version = match generator::generator_version(generator) {
+        Some(version) => version,
+        None => bail!(
+            "unknown generator \"{}\", available: {}",
+            generator,
+            GENERATORS
+                .iter()
+                .map(|generator| generator.0)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    if Path::new(dir).exists() {
+        bail!("{} already exists", dir);
+    }
+    let mut meta = WorldMeta::new(seed);
+    meta.generator = String::from(generator);
+    meta.generator_version = version;
+    meta.save(dir)
+}
+pub fn delete_world(saves_dir: &str, name: &str) -> Result<()> {
+    check_world_name(name)?;
+    let dir = &world_dir(saves_dir, name);
+    if !is_world(dir) {
+        bail!("{} is not a world", dir);
+    }
+    fs::remove_dir_all(dir).with_context(|| format!("could not delete {}", dir))
+}
+pub fn copy_world(saves_dir: &str, from: &str, to: &str) -> Result<()> {
+    check_world_name(from)?;
+    check_world_name(to)?;
+    let (from, to) = (&world_dir(saves_dir, from), &world_dir(saves_dir, to));
+    if !is_world(from) {
+        bail!("{} is not a world", from);
+    }
+    if Path::new(to).exists() {
+        bail!("{} already exists", to);
+    }
+    copy_dir(Path::new(from), Path::new(to)).with_context(|| format!("could not copy {}", from))
+}
+// moves a world saved by an older version in legacy_dir to the world "world" of the saves directory,
+// returns where it went, nothing is moved when there is no such world or the target exists
+pub fn migrate_legacy_save(legacy_dir: &str, saves_dir: &str) -> Result<Option<String>> {
+    if !is_world(legacy_dir) {
+        return Ok(None);
+    }
+    let dir = world_dir(saves_dir, "world");
+    if Path::new(&dir).exists() {
+        bail!(
+            "{} holds a world of an older version, it was not moved to {} which already exists",
+            legacy_dir,
+            dir
+        );
+    }
+    fs::create_dir_all(saves_dir).with_context(|| format!("could not create {}", saves_dir))?;
+    // the saves directory may be on another file system
+    if fs::rename(legacy_dir, &dir).is_err() {
+        copy_dir(Path::new(legacy_dir), Path::new(&dir))
+            .and_then(|_| fs::remove_dir_all(legacy_dir))
+            .with_context(|| format!("could not move {} to {}", legacy_dir, dir))?;
+    }
+    Ok(Some(dir))
+}
+// shadow files of interrupted writes are left behind
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir(&path, &to.join(entry.file_name()))?;
+        } else if !path.extension().is_some_and(|ext| ext == "tmp") {
+            fs::copy(&path, to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
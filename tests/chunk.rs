@@ -0,0 +1,111 @@
+mod common;
+
+use glam::{uvec3, IVec3, UVec3};
+use rust_voxel_engine::block::Block;
+use rust_voxel_engine::chunk::{self, Chunk};
+
+fn assert_heights(chunk: &Chunk, height: impl Fn(u32, u32) -> u32) {
+    for z in 0..32 {
+        for x in 0..32 {
+            assert_eq!(chunk.height(x, z), height(x, z), "column {},{}", x, z);
+        }
+    }
+}
+
+// the whole chunk edits replace the palette instead of the blocks
+#[test]
+fn heights_follow_bulk_edits() {
+    let registry = common::registry();
+    let (stone, dirt, air) = (
+        registry.block("stone"),
+        registry.block("dirt"),
+        Block::new(0),
+    );
+    let all = (UVec3::ZERO, UVec3::splat(31));
+    let mut chunk = Chunk::new();
+    chunk.compute_heightmap(&registry);
+
+    chunk.fill(all.0, all.1, stone, &registry);
+    assert_heights(&chunk, |_, _| 32);
+    chunk.replace(all.0, all.1, stone, air, &registry);
+    assert_heights(&chunk, |_, _| 0);
+    chunk.replace(all.0, all.1, air, stone, &registry);
+    assert_heights(&chunk, |_, _| 32);
+
+    chunk.fill(uvec3(0, 20, 0), uvec3(31, 31, 15), air, &registry);
+    assert_heights(&chunk, |_, z| if z < 16 { 20 } else { 32 });
+    chunk.replace(all.0, all.1, stone, dirt, &registry);
+    assert_heights(&chunk, |_, z| if z < 16 { 20 } else { 32 });
+    chunk.replace(uvec3(0, 10, 0), uvec3(31, 31, 31), dirt, air, &registry);
+    assert_heights(&chunk, |_, _| 10);
+    chunk.apply(uvec3(4, 0, 4), uvec3(4, 31, 4), &registry, |pos, block| {
+        if pos.y == 25 {
+            stone
+        } else {
+            block
+        }
+    });
+    assert_heights(&chunk, |x, z| if (x, z) == (4, 4) { 26 } else { 10 });
+    chunk.fill(all.0, all.1, air, &registry);
+    assert_heights(&chunk, |_, _| 0);
+}
+
+fn blocks(chunk: &Chunk) -> Vec<Block> {
+    let mut blocks = vec![];
+    for z in 0..32 {
+        for y in 0..32 {
+            for x in 0..32 {
+                blocks.push(chunk.get_block(uvec3(x, y, z)));
+            }
+        }
+    }
+    blocks
+}
+
+#[test]
+fn compaction_drops_unused_palette_entries() {
+    let mut chunk = Chunk::new();
+    for z in 0..32 {
+        for x in 0..32 {
+            chunk.set_block(uvec3(x, 0, z), Block::new(1 + (x + z * 32) as u16 % 20));
+        }
+    }
+    assert_eq!(chunk.palette().len(), 21);
+    assert_eq!(chunk.bitsize(), 5);
+    // only air and two of the block types are left
+    for z in 0..32 {
+        for x in 0..32 {
+            let block_type = if (x + z) % 2 == 0 { 3 } else { 7 };
+            chunk.set_block(uvec3(x, 0, z), Block::new(block_type));
+        }
+    }
+    assert_eq!(chunk.palette().len(), 21);
+    let expected = blocks(&chunk);
+    chunk.compact();
+    assert_eq!(chunk.palette().len(), 3);
+    assert_eq!(chunk.bitsize(), 2);
+    assert!(blocks(&chunk) == expected);
+
+    // a chunk left with a single block is uniform
+    chunk.fill(
+        uvec3(0, 0, 0),
+        uvec3(31, 0, 31),
+        Block::new(0),
+        &common::registry(),
+    );
+    chunk.compact();
+    assert_eq!(chunk.palette().len(), 1);
+    assert_eq!(chunk.bitsize(), 0);
+    assert!(blocks(&chunk).iter().all(|block| block.block_type == 0));
+}
+
+#[test]
+fn neighborhood_order() {
+    let pos = IVec3::new(3, -2, 7);
+    let neighbors: Vec<IVec3> = chunk::neighborhood(pos).collect();
+    assert_eq!(neighbors.len(), 27);
+    assert_eq!(neighbors[13], pos);
+    for (i, neighbor) in neighbors.iter().enumerate() {
+        assert_eq!(chunk::neighbor_index(*neighbor - pos), i);
+    }
+}
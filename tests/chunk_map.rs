@@ -0,0 +1,58 @@
+mod common;
+
+use glam::{ivec3, uvec3, IVec3, UVec3};
+use rust_voxel_engine::chunk::Chunk;
+use rust_voxel_engine::chunk_map::ChunkMap;
+use rust_voxel_engine::registry::BlockRegistry;
+
+// a chunk filled with stone from its bottom to height, excluded
+fn chunk(height: u32, registry: &BlockRegistry) -> Box<Chunk> {
+    let mut chunk = Box::new(Chunk::new());
+    if height > 0 {
+        let max = uvec3(31, height - 1, 31);
+        chunk.fill(UVec3::ZERO, max, registry.block("stone"), registry);
+    }
+    chunk.compute_heightmap(registry);
+    chunk
+}
+
+#[test]
+fn heights_of_loaded_columns() {
+    let registry = common::registry();
+    let mut map = ChunkMap::new();
+    assert_eq!(map.height(0, 0), None);
+
+    // the highest chunk of the column is empty and loaded after the others
+    map.insert(ivec3(0, -1, 0), chunk(32, &registry));
+    map.insert(ivec3(0, 1, 0), chunk(0, &registry));
+    map.insert(ivec3(0, 0, 0), chunk(10, &registry));
+    for (x, z) in [(0, 0), (31, 31), (17, 4)] {
+        assert_eq!(map.height(x, z), Some(9));
+    }
+    assert_eq!(map.height(32, 0), None);
+
+    // a block above the others in another chunk of the column
+    map.get_chunk_mut(ivec3(0, 1, 0))
+        .unwrap()
+        .set_block(uvec3(3, 20, 5), registry.block("stone"));
+    map.get_chunk_mut(ivec3(0, 1, 0))
+        .unwrap()
+        .update_height(uvec3(3, 20, 5), &registry);
+    assert_eq!(map.height(3, 5), Some(52));
+    assert_eq!(map.height(4, 5), Some(9));
+
+    // negative columns and columns of air
+    map.insert(ivec3(-1, 2, -3), chunk(5, &registry));
+    assert_eq!(map.height(-1, -96), Some(68));
+    assert_eq!(map.height(-32, -65), Some(68));
+    map.insert(ivec3(5, 0, 5), chunk(0, &registry));
+    assert_eq!(map.height(160, 160), None);
+
+    // unloading the top chunks uncovers the ones below
+    map.unload(|pos| pos.y >= 0);
+    assert_eq!(map.height(3, 5), Some(-1));
+    assert_eq!(map.height(-1, -96), None);
+    map.unload(|pos| pos == ivec3(0, -1, 0));
+    assert_eq!(map.height(0, 0), None);
+    assert!(map.get_chunk(IVec3::ZERO).is_none());
+}
@@ -0,0 +1,24 @@
+// helpers shared by the integration tests, each test crate only uses some of them
+#![allow(dead_code)]
+
+use rust_voxel_engine::registry::BlockRegistry;
+use std::path::{Path, PathBuf};
+
+pub fn blocks_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("blocks.ron")
+}
+
+pub fn registry() -> BlockRegistry {
+    let mut mapping = vec![];
+    BlockRegistry::load(&blocks_path(), &mut mapping).unwrap()
+}
+
+// an empty directory for the saves of a test, left behind to be looked at when it fails
+pub fn save_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join("rust_voxel_engine_tests")
+        .join(format!("{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().into_owned()
+}
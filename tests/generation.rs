@@ -0,0 +1,518 @@
+mod common;
+
+use glam::{ivec3, uvec3, IVec3};
+use rust_voxel_engine::biome::BIOMES;
+use rust_voxel_engine::chunk::{self, Chunk};
+use rust_voxel_engine::decoration::{self, ColumnFeature, Feature, FeatureBlocks};
+use rust_voxel_engine::generator::{
+    create_generator, decorate_chunk, generate_chunk, position_seed, FlatGenerator, NoiseGenerator,
+    WorldGenerator, GENERATORS,
+};
+use rust_voxel_engine::geology::{ORES, STRATA};
+use rust_voxel_engine::registry::BlockRegistry;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+const SEED: u64 = 0x5EED_0F3A_711F;
+// the surface of the forest, desert, tundra, mountains and plains, the surface at the
+// origin, then caves through the strata
+const POSITIONS: [IVec3; 8] = [
+    ivec3(-120, 0, -109),
+    ivec3(-120, 0, -87),
+    ivec3(-120, 0, -76),
+    ivec3(-120, -3, 1),
+    ivec3(-120, 0, -98),
+    ivec3(0, -1, 0),
+    ivec3(3, -2, -7),
+    ivec3(20, -4, 20),
+];
+
+// the hash of a generated chunk without any block
+fn empty_hash(registry: &BlockRegistry) -> u32 {
+    let mut chunk = Chunk::new();
+    chunk.compact();
+    chunk.compute_heightmap(registry);
+    crc32fast::hash(&chunk.serialize())
+}
+
+fn chunk_hashes(generator: &dyn WorldGenerator, registry: &BlockRegistry) -> Vec<u32> {
+    POSITIONS
+        .iter()
+        .map(|pos| crc32fast::hash(&generate_chunk(generator, *pos, registry).serialize()))
+        .collect()
+}
+
+// the chunks at POSITIONS with their features, decorated from the base terrain around them
+fn decorated_hashes(generator: &dyn WorldGenerator, registry: &BlockRegistry) -> Vec<u32> {
+    POSITIONS
+        .iter()
+        .map(|pos| {
+            let mut neighbors = chunk::neighborhood(*pos);
+            let base = std::array::from_fn(|_| {
+                Arc::new(generate_chunk(
+                    generator,
+                    neighbors.next().unwrap(),
+                    registry,
+                ))
+            });
+            crc32fast::hash(&decorate_chunk(generator, *pos, &base, registry).serialize())
+        })
+        .collect()
+}
+
+// changes to the terrain have to raise the version of the generator and update these
+#[test]
+fn noise_golden_hashes() {
+    let registry = common::registry();
+    let generator = NoiseGenerator::new(SEED, NoiseGenerator::VERSION, &registry).unwrap();
+    let biomes: Vec<&str> = POSITIONS[..5]
+        .iter()
+        .map(|pos| generator.biome(pos.x * 32, pos.z * 32).unwrap().name)
+        .collect();
+    assert_eq!(
+        biomes,
+        ["forest", "desert", "tundra", "mountains", "plains"]
+    );
+    let hashes = chunk_hashes(&generator, &registry);
+    assert!(!hashes.contains(&empty_hash(&registry)));
+    assert_eq!(
+        hashes,
+        [
+            3842911917, 4273523105, 3484817897, 4123253346, 2482096033, 1513397543, 3826095982,
+            1095325133
+        ]
+    );
+}
+
+// worlds keep the version they were created with, so the older terrain must not change
+// either, the simd noise of v1 depends on the cpu so it has no hashes
+#[test]
+fn older_noise_versions_keep_their_terrain() {
+    let registry = common::registry();
+    let goldens: [(u32, [u32; 8]); 4] = [
+        (
+            2,
+            [
+                1986021890, 556717841, 305619929, 1963825561, 3091221543, 2666369068, 1296215851,
+                24349434,
+            ],
+        ),
+        (
+            3,
+            [
+                3842911917, 3498371355, 2290639519, 3595663764, 3091221543, 2666369068, 1296215851,
+                24349434,
+            ],
+        ),
+        (
+            4,
+            [
+                3842911917, 3498371355, 2290639519, 3595663764, 3091221543, 2666369068, 1296215851,
+                24349434,
+            ],
+        ),
+        (
+            5,
+            [
+                3842911917, 4273523105, 3484817897, 4123253346, 2482096033, 1513397543, 3826095982,
+                1095325133,
+            ],
+        ),
+    ];
+    for (version, golden) in goldens.iter() {
+        let generator = NoiseGenerator::new(SEED, *version, &registry).unwrap();
+        let hashes = chunk_hashes(&generator, &registry);
+        assert!(!hashes.contains(&empty_hash(&registry)), "v{}", version);
+        assert_eq!(hashes, golden, "v{}", version);
+    }
+}
+
+#[test]
+fn decorated_golden_hashes() {
+    let registry = common::registry();
+    let goldens: [(u32, [u32; 8]); 3] = [
+        (
+            4,
+            [
+                4157546099, 3498371355, 173044671, 3265960334, 1735817377, 3455644851, 1296215851,
+                24349434,
+            ],
+        ),
+        (
+            5,
+            [
+                4157546099, 4273523105, 219467814, 1587956343, 230811890, 667786554, 3826095982,
+                1095325133,
+            ],
+        ),
+        // the boulders of the caves are gone, none falls in these chunks
+        (
+            6,
+            [
+                4157546099, 4273523105, 219467814, 1587956343, 230811890, 667786554, 3826095982,
+                1095325133,
+            ],
+        ),
+    ];
+    for (version, golden) in goldens.iter() {
+        let generator = NoiseGenerator::new(SEED, *version, &registry).unwrap();
+        let hashes = decorated_hashes(&generator, &registry);
+        // the trees of the forest
+        assert_ne!(
+            hashes[0],
+            chunk_hashes(&generator, &registry)[0],
+            "v{}",
+            version
+        );
+        assert_eq!(hashes, golden, "v{}", version);
+    }
+}
+
+#[test]
+fn unknown_versions_are_refused() {
+    let registry = common::registry();
+    for (name, latest) in GENERATORS.iter() {
+        assert!(create_generator(name, *latest, SEED, &registry).is_ok());
+        assert!(create_generator(name, 0, SEED, &registry).is_err());
+        assert!(create_generator(name, latest + 1, SEED, &registry).is_err());
+    }
+    assert!(create_generator("caves", 1, SEED, &registry).is_err());
+}
+
+// the blocks of the world are still named but their definitions are gone
+#[test]
+fn generators_need_their_blocks() {
+    let mut mapping = vec![];
+    BlockRegistry::load(&common::blocks_path(), &mut mapping).unwrap();
+    let registry = BlockRegistry::new(vec![], &mut mapping).unwrap();
+    assert!(registry.id("stone").is_some());
+    for (name, version) in GENERATORS.iter() {
+        assert!(create_generator(name, *version, SEED, &registry).is_err());
+    }
+}
+
+#[test]
+fn flat_golden_hashes() {
+    let registry = common::registry();
+    let hashes = chunk_hashes(&FlatGenerator::new(&registry).unwrap(), &registry);
+    assert_eq!(
+        hashes,
+        [
+            3302786140, 3302786140, 3302786140, 457156790, 3302786140, 2173505666, 457156790,
+            457156790
+        ]
+    );
+}
+
+#[test]
+fn seeds_give_different_terrain() {
+    let registry = common::registry();
+    let hashes = chunk_hashes(
+        &NoiseGenerator::new(SEED, NoiseGenerator::VERSION, &registry).unwrap(),
+        &registry,
+    );
+    let other = chunk_hashes(
+        &NoiseGenerator::new(SEED + 1, NoiseGenerator::VERSION, &registry).unwrap(),
+        &registry,
+    );
+    assert_ne!(hashes, other);
+}
+
+#[test]
+fn same_terrain_on_every_thread() {
+    let registry = Arc::new(common::registry());
+    let expected = chunk_hashes(
+        &NoiseGenerator::new(SEED, NoiseGenerator::VERSION, &registry).unwrap(),
+        &registry,
+    );
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let registry = registry.clone();
+            std::thread::spawn(move || {
+                chunk_hashes(
+                    &NoiseGenerator::new(SEED, NoiseGenerator::VERSION, &registry).unwrap(),
+                    &registry,
+                )
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn surface_blocks_follow_the_biomes() {
+    let registry = common::registry();
+    let generator = NoiseGenerator::new(SEED, NoiseGenerator::VERSION, &registry).unwrap();
+    let mut seen = vec![false; BIOMES.len()];
+    for i in 0..16 {
+        let (chunk_x, chunk_z) = (i * 97 - 700, i * 131 - 1000);
+        let mut top = vec![None; 32 * 32];
+        for chunk_y in (-8..8).rev() {
+            let chunk = generate_chunk(&generator, ivec3(chunk_x, chunk_y, chunk_z), &registry);
+            for z in 0..32 {
+                for x in 0..32 {
+                    let height = chunk.height(x, z);
+                    if top[(x + z * 32) as usize].is_none() && height > 0 {
+                        top[(x + z * 32) as usize] = Some(chunk.get_block(uvec3(x, height - 1, z)));
+                    }
+                }
+            }
+        }
+        for z in 0..32 {
+            for x in 0..32 {
+                let biome = generator.biome(chunk_x * 32 + x, chunk_z * 32 + z).unwrap();
+                let index = BIOMES.iter().position(|b| b.name == biome.name).unwrap();
+                let block = top[(x + z * 32) as usize].unwrap();
+                // caves can open the filler and the strata below it
+                assert!([biome.surface, biome.filler]
+                    .iter()
+                    .copied()
+                    .chain(STRATA.iter().map(|stratum| stratum.block))
+                    .chain(ORES.iter().map(|ore| ore.block))
+                    .any(|name| registry.block(name) == block));
+                seen[index] |= registry.block(biome.surface) == block;
+            }
+        }
+    }
+    assert!(seen.iter().all(|seen| *seen));
+}
+
+// chunks of a forest of SEED decorated from the base terrain around them
+fn forest(registry: &BlockRegistry) -> HashMap<IVec3, Chunk> {
+    let generator = NoiseGenerator::new(SEED, NoiseGenerator::VERSION, registry).unwrap();
+    let mut base = HashMap::new();
+    for z in -6..0 {
+        for y in -4..4 {
+            for x in -41..-35 {
+                let pos = ivec3(x, y, z);
+                base.insert(pos, Arc::new(generate_chunk(&generator, pos, registry)));
+            }
+        }
+    }
+    let mut chunks = HashMap::new();
+    for z in -5..-1 {
+        for y in -3..3 {
+            for x in -40..-36 {
+                let pos = ivec3(x, y, z);
+                let mut positions = chunk::neighborhood(pos);
+                let neighbors = std::array::from_fn(|_| base[&positions.next().unwrap()].clone());
+                chunks.insert(pos, decorate_chunk(&generator, pos, &neighbors, registry));
+            }
+        }
+    }
+    chunks
+}
+
+#[test]
+fn trees_cross_chunk_borders() {
+    let registry = common::registry();
+    let chunks = forest(&registry);
+    let block = |pos: IVec3| {
+        chunks
+            .get(&(pos >> 5))
+            .map(|chunk| chunk.get_block((pos & 31).as_uvec3()))
+    };
+    let (log, leaves) = (registry.block("log"), registry.block("leaves"));
+    let (mut trees, mut crossing) = (0, 0);
+    // the chunks at the edge of the forest miss the features of the chunks around them
+    let inner = chunks.iter().filter(|(pos, _)| {
+        (-39..-37).contains(&pos.x) && (-2..2).contains(&pos.y) && (-4..-2).contains(&pos.z)
+    });
+    for (chunk_pos, chunk) in inner {
+        for z in 0..32 {
+            for y in 0..32 {
+                for x in 0..32 {
+                    let pos = *chunk_pos * 32 + ivec3(x as i32, y as i32, z as i32);
+                    let current = chunk.get_block(uvec3(x, y, z));
+                    if current == log
+                        && block(pos - ivec3(0, 1, 0)) == Some(registry.block("grass"))
+                    {
+                        trees += 1;
+                        // the whole crown is there, in whichever chunk it falls, the trunks
+                        // of the trees around can go through it
+                        let top = (1..8)
+                            .map(|dy| pos + ivec3(0, dy, 0))
+                            .find(|pos| block(*pos) != Some(log))
+                            .unwrap();
+                        assert_eq!(block(top), Some(leaves));
+                        for (dx, dz) in [(-2, 0), (2, 0), (0, -2), (0, 2)] {
+                            let side = top + ivec3(dx, -2, dz);
+                            assert!([Some(leaves), Some(log)].contains(&block(side)));
+                            crossing += (side >> 5 != *chunk_pos) as u32;
+                        }
+                    }
+                    if current == leaves {
+                        let near_log = (-2..3).any(|dz| {
+                            (-3..2).any(|dy| {
+                                (-2..3).any(|dx| block(pos + ivec3(dx, dy, dz)) == Some(log))
+                            })
+                        });
+                        assert!(near_log);
+                    }
+                }
+            }
+        }
+    }
+    assert!(trees > 10);
+    assert!(crossing > 0);
+}
+
+#[test]
+fn ores_cross_chunk_borders() {
+    let registry = common::registry();
+    let generator = NoiseGenerator::new(SEED, NoiseGenerator::VERSION, &registry).unwrap();
+    let ores: Vec<_> = ORES.iter().map(|ore| registry.block(ore.block)).collect();
+    let strata: Vec<_> = STRATA.iter().map(|s| registry.block(s.block)).collect();
+    let (mut ore_blocks, mut strata_blocks) = (vec![0; ores.len()], vec![0; strata.len()]);
+    let mut crossing = 0;
+    for z in 0..2 {
+        for y in -7..-2 {
+            let west = generate_chunk(&generator, ivec3(0, y, z), &registry);
+            let east = generate_chunk(&generator, ivec3(1, y, z), &registry);
+            for chunk in [&west, &east] {
+                for pos in (0..32 * 32 * 32).map(|i| uvec3(i & 31, i >> 5 & 31, i >> 10)) {
+                    let block = chunk.get_block(pos);
+                    if let Some(ore) = ores.iter().position(|ore| *ore == block) {
+                        ore_blocks[ore] += 1;
+                    }
+                    if let Some(stratum) = strata.iter().position(|s| *s == block) {
+                        strata_blocks[stratum] += 1;
+                    }
+                }
+            }
+            for pos in (0..32 * 32).map(|i| uvec3(31, i & 31, i >> 5)) {
+                let block = west.get_block(pos);
+                crossing += (ores.contains(&block)
+                    && east.get_block(uvec3(0, pos.y, pos.z)) == block)
+                    as u32;
+            }
+        }
+    }
+    assert!(ore_blocks.iter().all(|count| *count > 0));
+    assert!(strata_blocks.iter().all(|count| *count > 0));
+    assert!(crossing > 0);
+}
+
+// a boulder standing on the bottom layer of a chunk reaches down into the chunk below
+#[test]
+fn boulders_cross_vertical_chunk_borders() {
+    let registry = common::registry();
+    let (sand, stone) = (registry.block("sand"), registry.block("stone"));
+    let blocks = FeatureBlocks {
+        leaves: registry.block("leaves"),
+        log: registry.block("log"),
+        stone,
+    };
+    // a column whose boulder has a radius of 2
+    let ground = (4..28)
+        .map(|x| ivec3(x, 32, 10))
+        .find(|ground| position_seed(SEED, *ground) % 2 == 1)
+        .unwrap();
+    let empty = Arc::new(Chunk::new());
+    let mut ground_chunk = Chunk::new();
+    ground_chunk.set_block((ground & 31).as_uvec3(), sand);
+    let ground_chunk = Arc::new(ground_chunk);
+    let mut stones = HashSet::new();
+    for pos in [ivec3(0, 0, 0), ivec3(0, 1, 0)] {
+        let mut neighbors = chunk::neighborhood(pos);
+        let base: [Arc<Chunk>; 27] = std::array::from_fn(|_| {
+            if neighbors.next().unwrap() == ground >> 5 {
+                ground_chunk.clone()
+            } else {
+                empty.clone()
+            }
+        });
+        let mut chunk = (*base[13]).clone();
+        decoration::decorate(
+            SEED,
+            pos,
+            &base,
+            &mut chunk,
+            &blocks,
+            &|block| registry.is_solid(block),
+            &|_, _| {
+                Some(ColumnFeature {
+                    feature: Feature::Boulder,
+                    chance: 1.0,
+                    ground: sand,
+                    surface: Some(ground.y),
+                })
+            },
+        );
+        for local in (0..32 * 32 * 32).map(|i| uvec3(i & 31, i >> 5 & 31, i >> 10)) {
+            if chunk.get_block(local) == stone {
+                stones.insert(pos * 32 + local.as_ivec3());
+            }
+        }
+    }
+    // the whole ball around the block above the ground, the ground stays
+    let center = ground + ivec3(0, 1, 0);
+    let mut ball = HashSet::new();
+    for dz in -2..3 {
+        for dy in -2..3 {
+            for dx in -2..3 {
+                let pos = center + ivec3(dx, dy, dz);
+                if dx * dx + dy * dy + dz * dz < 6 && pos != ground {
+                    ball.insert(pos);
+                }
+            }
+        }
+    }
+    assert!(stones.contains(&(ground - ivec3(0, 1, 0))));
+    assert_eq!(stones, ball);
+}
+
+// the blocks the features of the mountains add to the base terrain, with how far each one is
+// under the highest solid block of its column
+fn mountain_features(version: u32, registry: &BlockRegistry) -> Vec<(IVec3, i32)> {
+    let generator = NoiseGenerator::new(SEED, version, registry).unwrap();
+    let (xs, ys, zs) = (-122..-117, -8..3, -1..4);
+    let mut base = HashMap::new();
+    for z in zs.clone() {
+        for y in ys.clone() {
+            for x in xs.clone() {
+                let pos = ivec3(x, y, z);
+                base.insert(pos, Arc::new(generate_chunk(&generator, pos, registry)));
+            }
+        }
+    }
+    let top = |pos: IVec3| {
+        ys.clone().rev().find_map(|y| {
+            let height = base[&ivec3(pos.x >> 5, y, pos.z >> 5)]
+                .height(pos.x as u32 & 31, pos.z as u32 & 31);
+            (height > 0).then(|| y * 32 + height as i32 - 1)
+        })
+    };
+    let mut added = vec![];
+    for z in zs.start + 1..zs.end - 1 {
+        for y in ys.start + 1..ys.end - 1 {
+            for x in xs.start + 1..xs.end - 1 {
+                let pos = ivec3(x, y, z);
+                let mut positions = chunk::neighborhood(pos);
+                let neighbors = std::array::from_fn(|_| base[&positions.next().unwrap()].clone());
+                let chunk = decorate_chunk(&generator, pos, &neighbors, registry);
+                for local in (0..32 * 32 * 32).map(|i| uvec3(i & 31, i >> 5 & 31, i >> 10)) {
+                    if chunk.get_block(local) != base[&pos].get_block(local) {
+                        let block_pos = pos * 32 + local.as_ivec3();
+                        added.push((block_pos, top(block_pos).unwrap() - block_pos.y));
+                    }
+                }
+            }
+        }
+    }
+    added
+}
+
+// mountains are stone down to the caves, their boulders were also placed on the cave floors
+#[test]
+fn boulders_stay_on_the_surface() {
+    let registry = common::registry();
+    // a boulder reaches 3 blocks above the ground, and 2 around it into the columns beside
+    let deep = |added: &[(IVec3, i32)]| added.iter().filter(|(_, depth)| *depth > 6).count();
+    let added = mountain_features(NoiseGenerator::VERSION, &registry);
+    assert!(!added.is_empty());
+    assert_eq!(deep(&added), 0);
+    assert!(deep(&mountain_features(5, &registry)) > 0);
+}
@@ -0,0 +1,217 @@
+mod common;
+
+use glam::{ivec3, uvec3, IVec3};
+use rust_voxel_engine::block::{Property, AXIS_X, AXIS_Z};
+use rust_voxel_engine::chunk::{self, Chunk};
+use rust_voxel_engine::generator::{generate_chunk, NoiseGenerator};
+use rust_voxel_engine::mesher::{
+    create_lod_mesh, create_mesh, create_mesh_per_block, merge_faces, Face, Lod,
+};
+use rust_voxel_engine::registry::BlockRegistry;
+use std::sync::Arc;
+
+// generated chunks around pos, changed by edit
+fn neighborhood<F: Fn(IVec3, &mut Chunk)>(
+    pos: IVec3,
+    registry: &BlockRegistry,
+    edit: F,
+) -> [Arc<Chunk>; 27] {
+    let generator = NoiseGenerator::new(42, NoiseGenerator::VERSION, registry).unwrap();
+    let neighbors: Vec<IVec3> = chunk::neighborhood(pos).collect();
+    std::array::from_fn(|i| {
+        let chunk_pos = neighbors[i];
+        let mut chunk = generate_chunk(&generator, chunk_pos, registry);
+        edit(chunk_pos, &mut chunk);
+        Arc::new(chunk)
+    })
+}
+
+fn assert_same_mesh(chunks: &[Arc<Chunk>; 27], registry: &BlockRegistry) -> usize {
+    let expected = create_mesh_per_block(chunks, registry);
+    let mesh = create_mesh(chunks, registry);
+    assert_eq!(mesh, expected);
+    expected
+        .faces
+        .iter()
+        .map(|faces| faces.len())
+        .sum::<usize>()
+        + expected.translucent.len()
+}
+
+#[test]
+fn generated_terrain() {
+    let registry = common::registry();
+    let mut faces = 0;
+    for pos in [
+        ivec3(0, 0, 0),
+        ivec3(3, -1, -7),
+        ivec3(-12, 1, 5),
+        ivec3(20, -2, 20),
+    ] {
+        faces += assert_same_mesh(&neighborhood(pos, &registry, |_, _| {}), &registry);
+    }
+    assert!(faces > 0);
+}
+
+#[test]
+fn transparent_and_rotated_blocks() {
+    let registry = common::registry();
+    let blocks = [
+        registry.block("leaves"),
+        registry.block("glass"),
+        registry.block("log").with(Property::Axis, AXIS_X),
+        registry.block("log").with(Property::Axis, AXIS_Z),
+        registry.block("air"),
+    ];
+    for pos in [ivec3(0, 0, 0), ivec3(3, -1, -7)] {
+        let chunks = neighborhood(pos, &registry, |chunk_pos, chunk| {
+            let mut state = (chunk_pos.x * 31 + chunk_pos.y * 17 + chunk_pos.z) as u64;
+            for _ in 0..3000 {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let pos = uvec3(
+                    (state >> 33) as u32 & 31,
+                    (state >> 43) as u32 & 31,
+                    (state >> 53) as u32 & 31,
+                );
+                chunk.set_block(pos, blocks[(state >> 20) as usize % blocks.len()]);
+            }
+            // a pane of glass across the chunk border
+            chunk.fill(uvec3(28, 10, 0), uvec3(31, 12, 31), blocks[1], &registry);
+        });
+        assert_same_mesh(&chunks, &registry);
+    }
+}
+
+#[test]
+fn missing_neighbors() {
+    let registry = common::registry();
+    let mut chunks = neighborhood(ivec3(1, 0, 1), &registry, |_, _| {});
+    for (i, chunk) in chunks.iter_mut().enumerate() {
+        if i != 13 && i % 2 == 0 {
+            *chunk = Arc::new(Chunk::new());
+        }
+    }
+    assert_same_mesh(&chunks, &registry);
+}
+
+// the faces of one block covered by a merged face
+fn split_face(face: &Face, dir: usize) -> Vec<Face> {
+    let (u_axis, v_axis) = [(2, 1), (2, 1), (0, 1), (0, 1), (2, 0), (2, 0)][dir];
+    let mut faces = vec![];
+    for v in 0..=(face.size >> 5 & 31) {
+        for u in 0..=(face.size & 31) {
+            let pos_dir_tex = face.pos_dir_tex + (u << (u_axis * 6)) + (v << (v_axis * 6));
+            faces.push(Face {
+                pos_dir_tex,
+                size: 0,
+                ..*face
+            });
+        }
+    }
+    faces
+}
+
+#[test]
+fn merged_faces_cover_the_same_blocks() {
+    let registry = common::registry();
+    let count = |faces: &[Vec<Face>; 6]| faces.iter().map(|faces| faces.len()).sum::<usize>();
+    let (mut merged, mut single) = (0, 0);
+    for pos in [ivec3(0, 0, 0), ivec3(3, -1, -7), ivec3(-12, 1, 5)] {
+        let chunks = neighborhood(pos, &registry, |_, _| {});
+        let expected = create_mesh(&chunks, &registry);
+        let mut mesh = create_mesh(&chunks, &registry);
+        merge_faces(&mut mesh);
+        for dir in 0..6 {
+            let mut faces: Vec<Face> = mesh.faces[dir]
+                .iter()
+                .flat_map(|face| split_face(face, dir))
+                .collect();
+            let mut expected_faces = expected.faces[dir].clone();
+            faces.sort_unstable_by_key(|face| face.pos_dir_tex);
+            expected_faces.sort_unstable_by_key(|face| face.pos_dir_tex);
+            assert_eq!(faces, expected_faces);
+        }
+        assert_eq!(mesh.translucent, expected.translucent);
+        merged += count(&mesh.faces);
+        single += count(&expected.faces);
+    }
+    assert!(merged < single);
+}
+
+// a chunk of stone whose neighbors at the indices of filled are stone too
+fn stone_chunk(registry: &BlockRegistry, filled: &[usize]) -> [Arc<Chunk>; 27] {
+    std::array::from_fn(|i| {
+        let mut chunk = Chunk::new();
+        if i == 13 || filled.contains(&i) {
+            chunk.fill(
+                uvec3(0, 0, 0),
+                uvec3(31, 31, 31),
+                registry.block("stone"),
+                registry,
+            );
+        }
+        Arc::new(chunk)
+    })
+}
+
+#[test]
+fn lod_faces_cover_cells() {
+    let registry = common::registry();
+    let chunks = stone_chunk(&registry, &[]);
+    for level in 0..4 {
+        let mesh = create_lod_mesh(
+            &chunks,
+            &registry,
+            Lod {
+                level,
+                seams: [false; 4],
+            },
+        );
+        let cells = 32 >> level;
+        let scale = 1 << level;
+        for (dir, faces) in mesh.faces.iter().enumerate() {
+            // faces toward the negative sides of the chunk belong to its neighbors
+            let expected = if dir % 2 == 1 { cells * cells } else { 0 };
+            assert_eq!(faces.len(), expected);
+            for face in faces {
+                assert_eq!(face.size, (scale - 1) | (scale - 1) << 5);
+                // the faces of the cells on the far side lie on the last block
+                let pos = [
+                    face.pos_dir_tex & 63,
+                    face.pos_dir_tex >> 6 & 63,
+                    face.pos_dir_tex >> 12 & 63,
+                ];
+                assert_eq!(pos[[0, 0, 2, 2, 1, 1][dir]], 31);
+            }
+        }
+    }
+}
+
+#[test]
+fn lod_seams() {
+    let registry = common::registry();
+    // the horizontal neighbors are filled so only the seams show faces
+    let chunks = stone_chunk(&registry, &[12, 14, 4, 22]);
+    let mesh = create_lod_mesh(
+        &chunks,
+        &registry,
+        Lod {
+            level: 1,
+            seams: [true, false, true, true],
+        },
+    );
+    let counts: Vec<usize> = mesh.faces.iter().map(|faces| faces.len()).collect();
+    assert_eq!(counts, [256, 0, 256, 256, 0, 256]);
+    for face in &mesh.faces[0] {
+        assert_eq!(face.pos_dir_tex & 63, 0);
+    }
+    for face in &mesh.faces[2] {
+        assert_eq!(face.pos_dir_tex >> 12 & 63, 0);
+    }
+    assert_eq!(
+        create_lod_mesh(&chunks, &registry, Lod::default()),
+        create_mesh(&chunks, &registry)
+    );
+}
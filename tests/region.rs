@@ -0,0 +1,325 @@
+mod common;
+
+use glam::{ivec3, uvec3, IVec3};
+use rust_voxel_engine::block::Block;
+use rust_voxel_engine::chunk::Chunk;
+use rust_voxel_engine::region::{region_filename, Codec, Region, RegionReader, REGION_VERSION};
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+// layout of the current region format, the index follows the header
+const HEADER_SIZE: u64 = 64;
+const INDEX_SIZE: usize = 8192;
+
+// a chunk with a few layers of different blocks, compacted like saved chunks are
+fn test_chunk(seed: u32) -> Box<Chunk> {
+    let mut chunk = Box::new(Chunk::new());
+    for z in 0..32 {
+        for x in 0..32 {
+            for y in 0..(x + z + seed) % 32 {
+                let block = 1 + (x * 7 + y * 3 + z + seed) % 5;
+                chunk.set_block(uvec3(x, y, z), Block::new(block as u16));
+            }
+        }
+    }
+    chunk.compact();
+    chunk
+}
+
+fn open(dir: &str, codec: Codec) -> Region {
+    Region::new(String::from(dir), IVec3::ZERO, codec, "noise").unwrap()
+}
+
+fn assert_loads(region: &mut Region, pos: IVec3, expected: &Chunk) {
+    let chunk = region.load_chunk(pos).unwrap().unwrap();
+    assert_eq!(chunk.serialize(), expected.serialize(), "chunk {}", pos);
+}
+
+fn write_at(filename: &str, offset: u64, bytes: &[u8]) {
+    let mut file = fs::OpenOptions::new().write(true).open(filename).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+// a crash after the journal was synced but while the index was being written
+#[test]
+fn journal_is_replayed_after_a_crash() {
+    let dir = common::save_dir("journal_replay");
+    let filename = region_filename(&dir, IVec3::ZERO);
+    let chunk = test_chunk(3);
+    {
+        let mut region = open(&dir, Codec::Lz4);
+        region.save_chunk(chunk.clone(), ivec3(1, 2, 3)).unwrap();
+    }
+    let file = fs::read(&filename).unwrap();
+    let index = &file[HEADER_SIZE as usize..HEADER_SIZE as usize + INDEX_SIZE];
+    let mut journal = index.to_vec();
+    journal.extend_from_slice(&crc32fast::hash(index).to_le_bytes());
+    fs::write(filename.clone() + ".journal", journal).unwrap();
+    write_at(&filename, HEADER_SIZE, &[0; INDEX_SIZE / 2]);
+
+    let mut region = open(&dir, Codec::Lz4);
+    assert!(!Path::new(&(filename.clone() + ".journal")).exists());
+    assert_loads(&mut region, ivec3(1, 2, 3), &chunk);
+}
+
+// a crash while the journal itself was written leaves the committed index
+#[test]
+fn incomplete_journal_is_discarded() {
+    let dir = common::save_dir("journal_discard");
+    let filename = region_filename(&dir, IVec3::ZERO);
+    let chunk = test_chunk(5);
+    {
+        let mut region = open(&dir, Codec::Lz4);
+        region.save_chunk(chunk.clone(), ivec3(4, 0, 9)).unwrap();
+    }
+    fs::write(filename.clone() + ".journal", [0xff; 100]).unwrap();
+
+    let mut region = open(&dir, Codec::Lz4);
+    assert!(!Path::new(&(filename + ".journal")).exists());
+    assert_loads(&mut region, ivec3(4, 0, 9), &chunk);
+}
+
+#[test]
+fn corrupted_records_are_quarantined() {
+    let dir = common::save_dir("quarantine");
+    let filename = region_filename(&dir, IVec3::ZERO);
+    let (chunk, other) = (test_chunk(7), test_chunk(11));
+    let mut region = open(&dir, Codec::None);
+    region.save_chunk(chunk.clone(), ivec3(2, 1, 2)).unwrap();
+    region.save_chunk(other.clone(), ivec3(3, 1, 2)).unwrap();
+    region.flush().unwrap();
+
+    // flipped on disk behind the back of the open region
+    let entry = RegionReader::open(&filename)
+        .unwrap()
+        .entry(ivec3(2, 1, 2))
+        .unwrap();
+    write_at(&filename, entry.offset as u64 + 20, b"garbage");
+    assert!(region.load_chunk(ivec3(2, 1, 2)).is_err());
+    assert!(region.load_chunk(ivec3(2, 1, 2)).unwrap().is_none());
+    assert_loads(&mut region, ivec3(3, 1, 2), &other);
+    let quarantined: Vec<_> = fs::read_dir(dir.clone() + "/quarantine")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(quarantined.len(), 1);
+    assert!(quarantined[0].starts_with("2,1,2-"));
+    drop(region);
+
+    // the chunk stays removed, and records corrupted while closed are caught when opened
+    let entry = RegionReader::open(&filename)
+        .unwrap()
+        .entry(ivec3(3, 1, 2))
+        .unwrap();
+    write_at(&filename, entry.offset as u64 + 20, b"garbage");
+    let mut region = open(&dir, Codec::None);
+    assert!(region.load_chunk(ivec3(2, 1, 2)).unwrap().is_none());
+    assert!(region.load_chunk(ivec3(3, 1, 2)).unwrap().is_none());
+    assert_eq!(fs::read_dir(dir + "/quarantine").unwrap().count(), 2);
+}
+
+#[test]
+fn chunks_round_trip_through_every_codec() {
+    for codec in [Codec::None, Codec::Lz4, Codec::Zstd] {
+        let dir = common::save_dir(&format!("codec_{:?}", codec));
+        let chunks: Vec<_> = (0..4).map(test_chunk).collect();
+        {
+            let mut region = open(&dir, codec);
+            for (i, chunk) in chunks.iter().enumerate() {
+                region
+                    .save_chunk(chunk.clone(), ivec3(i as i32, 0, 0))
+                    .unwrap();
+            }
+            for (i, chunk) in chunks.iter().enumerate() {
+                assert_loads(&mut region, ivec3(i as i32, 0, 0), chunk);
+            }
+        }
+        let mut region = open(&dir, codec);
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_loads(&mut region, ivec3(i as i32, 0, 0), chunk);
+        }
+        // records keep their codec when the region is opened with another one
+        let mut region = open(&dir, Codec::Zstd);
+        assert_loads(&mut region, ivec3(0, 0, 0), &chunks[0]);
+    }
+}
+
+// tests/fixtures/region_v0 is a headerless region with 16 bit palettes: a raw record at
+// 0,0,0 holding stone under y = 10, grass on it and air above, a checksummed record at
+// 1,0,0 full of dirt and a checksummed record with a codec id at 0,1,0 alternating air
+// and stone
+#[test]
+fn headerless_regions_are_migrated() {
+    let dir = common::save_dir("migrate_v0");
+    let filename = region_filename(&dir, IVec3::ZERO);
+    fs::create_dir_all(dir.clone() + "/region").unwrap();
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/region_v0"),
+        &filename,
+    )
+    .unwrap();
+    let (air, grass, dirt, stone) = (Block::new(0), Block::new(1), Block::new(2), Block::new(3));
+    let check = |region: &mut Region| {
+        let terrain = region.load_chunk(ivec3(0, 0, 0)).unwrap().unwrap();
+        let full = region.load_chunk(ivec3(1, 0, 0)).unwrap().unwrap();
+        let alternating = region.load_chunk(ivec3(0, 1, 0)).unwrap().unwrap();
+        assert!(region.load_chunk(ivec3(1, 1, 1)).unwrap().is_none());
+        for pos in (0..32 * 32 * 32).map(|i| uvec3(i & 31, i >> 5 & 31, i >> 10)) {
+            let expected = match pos.y {
+                y if y < 10 => stone,
+                10 => grass,
+                _ => air,
+            };
+            assert_eq!(terrain.get_block(pos), expected);
+            assert_eq!(full.get_block(pos), dirt);
+            let expected = [air, stone][((pos.x + pos.y + pos.z) & 1) as usize];
+            assert_eq!(alternating.get_block(pos), expected);
+        }
+    };
+    check(&mut open(&dir, Codec::Lz4));
+    assert_eq!(
+        RegionReader::open(&filename).unwrap().header.version,
+        REGION_VERSION
+    );
+    check(&mut open(&dir, Codec::Lz4));
+}
+
+// the journal of a crash before the migration belongs to the old file
+#[test]
+fn journal_is_applied_before_migrating() {
+    let dir = common::save_dir("migrate_journal");
+    let filename = region_filename(&dir, IVec3::ZERO);
+    fs::create_dir_all(dir.clone() + "/region").unwrap();
+    let fixture =
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/region_v0")).unwrap();
+    fs::write(&filename, &fixture).unwrap();
+    // the committed index dropped the chunk at 1,0,0, it is still in the index of the file
+    let mut index = fixture[..INDEX_SIZE].to_vec();
+    index[8..16].copy_from_slice(&[0; 8]);
+    let mut journal = index.clone();
+    journal.extend_from_slice(&crc32fast::hash(&index).to_le_bytes());
+    fs::write(filename.clone() + ".journal", journal).unwrap();
+
+    for _ in 0..2 {
+        let mut region = open(&dir, Codec::Lz4);
+        assert!(!Path::new(&(filename.clone() + ".journal")).exists());
+        assert!(region.load_chunk(ivec3(0, 0, 0)).unwrap().is_some());
+        assert!(region.load_chunk(ivec3(1, 0, 0)).unwrap().is_none());
+        assert!(region.load_chunk(ivec3(0, 1, 0)).unwrap().is_some());
+    }
+}
+
+// a chunk whose serialized size grows with height, its blocks are spread over the whole palette
+fn sized_chunk(seed: u32, height: u32) -> Box<Chunk> {
+    let mut chunk = Box::new(Chunk::new());
+    let mut state = seed as u64 + 1;
+    for y in 0..height {
+        for z in 0..32 {
+            for x in 0..32 {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                chunk.set_block(uvec3(x, y, z), Block::new(1 + (state >> 40) as u16 % 12));
+            }
+        }
+    }
+    chunk.compact();
+    chunk
+}
+
+// the records of the index lie after it, inside the file and don't share sectors
+fn assert_no_overlap(filename: &str) {
+    let reader = RegionReader::open(filename).unwrap();
+    let mut entries = reader.entries();
+    entries.sort_by_key(|entry| entry.offset);
+    let mut end = HEADER_SIZE + INDEX_SIZE as u64;
+    for entry in &entries {
+        assert!(entry.offset as u64 >= end, "chunk {} overlaps", entry.id);
+        end = entry.offset as u64 + entry.size as u64;
+    }
+    assert!(end <= reader.file_len().unwrap());
+}
+
+fn assert_all_load(region: &mut Region, chunks: &[(IVec3, Box<Chunk>)]) {
+    for (pos, chunk) in chunks {
+        assert_loads(region, *pos, chunk);
+    }
+}
+
+#[test]
+fn freed_sectors_are_reused_and_compacted() {
+    let dir = common::save_dir("free_list");
+    let filename = region_filename(&dir, IVec3::ZERO);
+    let pos = |i: u32| ivec3(i as i32 % 16, i as i32 / 16 % 4, i as i32 / 64);
+    let mut chunks: Vec<(IVec3, Box<Chunk>)> =
+        (0..24).map(|i| (pos(i), sized_chunk(i, 8))).collect();
+    let mut region = open(&dir, Codec::None);
+    for (pos, chunk) in &chunks {
+        region.save_chunk(chunk.clone(), *pos).unwrap();
+    }
+    region.flush().unwrap();
+    let committed = chunks.clone();
+
+    // larger and smaller chunks in place of the committed ones, twice before the flush
+    for round in 0..2 {
+        for (i, (pos, chunk)) in chunks.iter_mut().enumerate() {
+            let height = if i % 2 == 0 {
+                12 + round * 8
+            } else {
+                1 + round
+            };
+            *chunk = sized_chunk(i as u32 + 100, height);
+            region.save_chunk(chunk.clone(), *pos).unwrap();
+        }
+    }
+    // the committed records are still whole until the flush
+    let mut reader = RegionReader::open(&filename).unwrap();
+    assert!(reader.verify().is_empty());
+    for (pos, chunk) in &committed {
+        let entry = reader.entry(*pos).unwrap();
+        assert_eq!(reader.read_chunk(&entry).unwrap(), chunk.serialize());
+    }
+    region.flush().unwrap();
+    assert_no_overlap(&filename);
+    assert_all_load(&mut region, &chunks);
+    drop(region);
+
+    // the free list is rebuilt from the index when the region is opened again
+    let mut region = open(&dir, Codec::None);
+    assert_all_load(&mut region, &chunks);
+    for (i, (pos, chunk)) in chunks.iter_mut().enumerate().skip(3).step_by(4) {
+        *chunk = sized_chunk(i as u32 + 200, 6);
+        region.save_chunk(chunk.clone(), *pos).unwrap();
+    }
+    region.flush().unwrap();
+    assert_no_overlap(&filename);
+    assert_all_load(&mut region, &chunks);
+
+    // shrinking the first records leaves holes that the last records are moved into
+    let reader = RegionReader::open(&filename).unwrap();
+    let mut entries = reader.entries();
+    entries.sort_by_key(|entry| entry.offset);
+    for entry in &entries[..8] {
+        let (pos, chunk) = chunks
+            .iter_mut()
+            .find(|(pos, _)| reader.entry(*pos).unwrap().id == entry.id)
+            .unwrap();
+        *chunk = sized_chunk(entry.id as u32, 1);
+        region.save_chunk(chunk.clone(), *pos).unwrap();
+    }
+    region.flush().unwrap();
+    let before = fs::metadata(&filename).unwrap().len();
+    while region.compact_step().unwrap() {}
+    region.flush().unwrap();
+    let after = fs::metadata(&filename).unwrap().len();
+    assert!(after < before, "{} -> {} bytes", before, after);
+    assert_no_overlap(&filename);
+    assert!(RegionReader::open(&filename).unwrap().verify().is_empty());
+    assert_all_load(&mut region, &chunks);
+    drop(region);
+
+    let mut region = open(&dir, Codec::None);
+    assert_all_load(&mut region, &chunks);
+}
@@ -0,0 +1,185 @@
+use glam::{ivec3, uvec3, IVec3};
+use rust_voxel_engine::block::Block;
+use rust_voxel_engine::vox::{VoxMapping, VoxModel};
+
+// a model wider than a MagicaVoxel model, with mapped and unmapped block types and empty voxels
+fn test_model() -> VoxModel {
+    let mut model = VoxModel::new(uvec3(300, 20, 6));
+    for z in 0..6 {
+        for y in 0..20 {
+            for x in 0..300 {
+                let block_type = match (x + y * 3 + z * 7) % 9 {
+                    7 => 0,
+                    8 => 42,
+                    t => 1 + t % 6,
+                };
+                model.set_block(uvec3(x, y, z), Block::new(block_type as u16));
+            }
+        }
+    }
+    // the corners are set so the imported bounds are the same
+    for corner in 0..8 {
+        let pos = uvec3(corner & 1, corner >> 1 & 1, corner >> 2) * uvec3(299, 19, 5);
+        model.set_block(pos, Block::new(3));
+    }
+    model
+}
+
+#[test]
+fn models_round_trip() {
+    let mapping = VoxMapping::new();
+    let model = test_model();
+    let bytes = model.to_bytes(&mapping).unwrap();
+    let loaded = VoxModel::from_bytes(&bytes, &mapping).unwrap();
+    assert_eq!(loaded.size, model.size);
+    assert!(loaded.blocks == model.blocks);
+}
+
+fn chunk(id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut buffer = id.to_vec();
+    buffer.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&0u32.to_le_bytes());
+    buffer.extend_from_slice(content);
+    buffer
+}
+
+fn ints(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let content = chunks.concat();
+    let mut buffer = b"VOX ".to_vec();
+    buffer.extend_from_slice(&150u32.to_le_bytes());
+    buffer.extend_from_slice(b"MAIN");
+    buffer.extend_from_slice(&ints(&[0, content.len() as i32]));
+    buffer.extend(content);
+    buffer
+}
+
+// a 2x2x2 model with one voxel
+fn one_voxel() -> Vec<Vec<u8>> {
+    vec![
+        chunk(b"SIZE", &ints(&[2, 2, 2])),
+        chunk(b"XYZI", &[1, 0, 0, 0, 1, 0, 0, 1]),
+    ]
+}
+
+fn transform(id: i32, child: i32, translation: Option<IVec3>) -> Vec<u8> {
+    let mut content = ints(&[id, 0, child, -1, 0, 1]);
+    match translation {
+        Some(t) => {
+            let value = format!("{} {} {}", t.x, t.y, t.z);
+            content.extend(ints(&[1, 2]));
+            content.extend_from_slice(b"_t");
+            content.extend(ints(&[value.len() as i32]));
+            content.extend_from_slice(value.as_bytes());
+        }
+        None => content.extend(ints(&[0])),
+    }
+    chunk(b"nTRN", &content)
+}
+
+fn group(id: i32, children: &[i32]) -> Vec<u8> {
+    let mut content = ints(&[id, 0, children.len() as i32]);
+    content.extend(ints(children));
+    chunk(b"nGRP", &content)
+}
+
+fn shape(id: i32) -> Vec<u8> {
+    chunk(b"nSHP", &ints(&[id, 0, 1, 0, 0]))
+}
+
+fn load(chunks: &[Vec<u8>]) -> anyhow::Result<VoxModel> {
+    VoxModel::from_bytes(&file(chunks), &VoxMapping::new())
+}
+
+#[test]
+fn scene_graph() {
+    let mut chunks = one_voxel();
+    chunks.extend([
+        transform(0, 1, None),
+        group(1, &[2, 4]),
+        transform(2, 3, Some(ivec3(0, 0, 0))),
+        shape(3),
+        transform(4, 5, Some(ivec3(4, 0, 0))),
+        shape(5),
+    ]);
+    let model = load(&chunks).unwrap();
+    assert_eq!(model.size, uvec3(5, 1, 1));
+    let block = model.get_block(uvec3(0, 0, 0));
+    assert_ne!(block.block_type, 0);
+    assert!(model.get_block(uvec3(4, 0, 0)) == block);
+    assert_eq!(model.get_block(uvec3(2, 0, 0)).block_type, 0);
+}
+
+#[test]
+fn malformed_files_are_errors() {
+    // negative voxel count
+    assert!(load(&[
+        chunk(b"SIZE", &ints(&[2, 2, 2])),
+        chunk(b"XYZI", &ints(&[-1]))
+    ])
+    .is_err());
+    // negative model size
+    assert!(load(&[
+        chunk(b"SIZE", &ints(&[-2, 2, 2])),
+        chunk(b"XYZI", &ints(&[0]))
+    ])
+    .is_err());
+    // negative group child count
+    let mut chunks = one_voxel();
+    chunks.extend([transform(0, 1, None), chunk(b"nGRP", &ints(&[1, 0, -5]))]);
+    assert!(load(&chunks).is_err());
+    // a chunk longer than the file
+    let mut bytes = file(&one_voxel());
+    bytes.extend(chunk(b"XYZI", &ints(&[1])));
+    let len = bytes.len();
+    bytes[len - 12..len - 8].copy_from_slice(&i32::MAX.to_le_bytes());
+    assert!(VoxModel::from_bytes(&bytes, &VoxMapping::new()).is_err());
+}
+
+#[test]
+fn truncated_files_dont_panic() {
+    let mapping = VoxMapping::new();
+    let bytes = test_model().to_bytes(&mapping).unwrap();
+    for len in (0..bytes.len()).step_by(7) {
+        let _ = VoxModel::from_bytes(&bytes[..len], &mapping);
+    }
+    // inside the voxels of the first model
+    assert!(VoxModel::from_bytes(&bytes[..100], &mapping).is_err());
+}
+
+#[test]
+fn cyclic_scene_graphs_are_errors() {
+    let mut chunks = one_voxel();
+    chunks.push(transform(0, 0, None));
+    assert!(load(&chunks).is_err());
+
+    let mut chunks = one_voxel();
+    chunks.extend([transform(0, 1, None), group(1, &[2]), transform(2, 1, None)]);
+    assert!(load(&chunks).is_err());
+}
+
+// translations at the bounds of i32 neither overflow nor allocate a huge model
+#[test]
+fn distant_models_are_errors() {
+    let mut chunks = one_voxel();
+    chunks.extend([
+        transform(0, 1, None),
+        group(1, &[2, 4]),
+        transform(2, 3, Some(IVec3::splat(i32::MIN))),
+        shape(3),
+        transform(4, 5, Some(IVec3::splat(i32::MAX))),
+        shape(5),
+    ]);
+    assert!(load(&chunks).is_err());
+
+    let mut chunks = one_voxel();
+    chunks.push(transform(0, 1, Some(IVec3::splat(i32::MAX))));
+    for id in 1..40 {
+        chunks.push(transform(id, id + 1, Some(IVec3::splat(i32::MAX))));
+    }
+    chunks.push(shape(40));
+    assert!(load(&chunks).is_err());
+}
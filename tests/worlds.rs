@@ -0,0 +1,83 @@
+mod common;
+
+use rust_voxel_engine::world_meta::WorldMeta;
+use rust_voxel_engine::worlds;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn metadata_round_trips() {
+    let dir = common::save_dir("meta_round_trip") + "/world";
+    let mut meta = WorldMeta::new(1234);
+    meta.player.pos = [1.0, 2.0, 3.0];
+    meta.save(&dir).unwrap();
+    let loaded = WorldMeta::load(&dir).unwrap();
+    assert_eq!(loaded.seed, 1234);
+    assert_eq!(loaded.player.pos, [1.0, 2.0, 3.0]);
+}
+
+// a save that can't be written is reported and leaves the previous one
+#[test]
+fn failed_metadata_saves_are_errors() {
+    let dir = common::save_dir("meta_failed_save");
+    fs::write(dir.clone() + "/file", "").unwrap();
+    assert!(WorldMeta::new(1)
+        .save(&(dir.clone() + "/file/world"))
+        .is_err());
+
+    let world = dir + "/world";
+    WorldMeta::new(1).save(&world).unwrap();
+    fs::create_dir(world.clone() + "/world.ron.tmp").unwrap();
+    assert!(WorldMeta::new(2).save(&world).is_err());
+    assert_eq!(WorldMeta::load(&world).unwrap().seed, 1);
+}
+
+#[test]
+fn world_names_stay_in_the_saves_directory() {
+    let saves = common::save_dir("world_names");
+    worlds::create_world(&worlds::world_dir(&saves, "world"), 1, "flat").unwrap();
+    for name in ["", "..", ".", "/", "/tmp", "a/b", "../world", "a\\b"] {
+        assert!(worlds::check_world_name(name).is_err(), "{:?}", name);
+        assert!(worlds::delete_world(&saves, name).is_err(), "{:?}", name);
+        assert!(
+            worlds::copy_world(&saves, "world", name).is_err(),
+            "{:?}",
+            name
+        );
+        assert!(
+            worlds::copy_world(&saves, name, "copy").is_err(),
+            "{:?}",
+            name
+        );
+    }
+    worlds::copy_world(&saves, "world", "copy").unwrap();
+    worlds::delete_world(&saves, "world").unwrap();
+    let names: Vec<String> = worlds::list_worlds(&saves)
+        .into_iter()
+        .map(|w| w.0)
+        .collect();
+    assert_eq!(names, ["copy"]);
+}
+
+#[test]
+fn legacy_saves_are_moved() {
+    let dir = common::save_dir("legacy_save");
+    let (legacy, saves) = (dir.clone() + "/save", dir + "/saves");
+    assert_eq!(worlds::migrate_legacy_save(&legacy, &saves).unwrap(), None);
+    WorldMeta::new(77).save(&legacy).unwrap();
+    fs::create_dir(legacy.clone() + "/region").unwrap();
+    fs::write(legacy.clone() + "/region/0,0,0", [1, 2, 3]).unwrap();
+
+    let moved = worlds::migrate_legacy_save(&legacy, &saves)
+        .unwrap()
+        .unwrap();
+    assert_eq!(moved, worlds::world_dir(&saves, "world"));
+    assert!(!Path::new(&legacy).exists());
+    assert_eq!(WorldMeta::load(&moved).unwrap().seed, 77);
+    assert_eq!(fs::read(moved + "/region/0,0,0").unwrap(), [1, 2, 3]);
+
+    // an existing world is never replaced
+    WorldMeta::new(78).save(&legacy).unwrap();
+    assert!(worlds::migrate_legacy_save(&legacy, &saves).is_err());
+    assert!(Path::new(&legacy).exists());
+}